@@ -0,0 +1,61 @@
+//! A UDT echo server running on the async-std executor.
+//!
+//! `UdtAsyncStream` implements the `futures` I/O traits, which async-std
+//! re-exports, so it works with async-std's extension traits as is.
+//!
+//! ```text
+//! cargo run --example async_std_echo
+//! ```
+
+use async_std::{io::prelude::*, task};
+use udt_rs::{error::UdtError, UdtAsyncStream};
+
+async fn echo(mut stream: UdtAsyncStream) -> std::io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        // UDT reports the client's close as ConnLost rather than end of stream.
+        let size = match stream.read(&mut buf).await {
+            Ok(size) => size,
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionAborted => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        stream.write_all(&buf[..size]).await?;
+    }
+}
+
+fn main() -> Result<(), UdtError> {
+    udt_rs::startup()?;
+    let (listener, addr) = udt_rs::async_builder().listen_with_addr("127.0.0.1:0", 16)?;
+    println!("listening on {}", addr);
+
+    task::block_on(async {
+        task::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        task::spawn(async move {
+                            if let Err(e) = echo(stream).await {
+                                eprintln!("{}: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("accept failed: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        // A blocking stream converts, e.g. after a synchronous handshake.
+        let stream = udt_rs::builder().connect(addr)?;
+        let mut client = UdtAsyncStream::from_blocking(stream)?;
+        client.write_all(b"hello over udt").await?;
+        let mut reply = [0; 14];
+        client.read_exact(&mut reply).await?;
+        println!("echoed: {}", String::from_utf8_lossy(&reply));
+        Ok::<(), UdtError>(())
+    })?;
+
+    udt_rs::cleanup()
+}
@@ -0,0 +1,30 @@
+use crate::{error, error::UdtError, UdtSocket};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use std::os::raw::c_char;
+
+type Result<T> = std::result::Result<T, UdtError>;
+
+impl UdtSocket {
+    /// Sends from a `Bytes` buffer, like `send`.
+    pub fn send_bytes(&self, buf: &Bytes) -> Result<usize> {
+        self.send(buf)
+    }
+    /// Receives straight into the spare capacity of `buf` and advances its
+    /// length by the number of bytes read. `buf` grows by a small amount if it
+    /// is full, so reserve space beforehand to receive more at once.
+    pub fn recv_bytes(&self, buf: &mut BytesMut) -> Result<usize> {
+        let chunk = buf.chunk_mut();
+        let len = chunk.len().min(i32::MAX as usize);
+        let result =
+            unsafe { udt_sys::udt_recv(self.id, chunk.as_mut_ptr() as *mut c_char, len as i32, 0) };
+        if result == unsafe { udt_sys::UDT_ERROR } {
+            error::get_error(0)
+        } else {
+            // UDT initialized the first `result` bytes of the chunk.
+            unsafe { buf.advance_mut(result as usize) };
+            Ok(result as usize)
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Write wrapper that coalesces small writes into fewer, larger ones.
+///
+/// Buffered data is written out once `threshold` bytes have accumulated, or
+/// `delay` after the first byte was buffered, whichever comes first. The delay
+/// is enforced by a background thread. Only the write half is wrapped: a
+/// stream used for reading as well has to be read through another handle.
+///
+/// Errors hit while writing buffered data out, by `write` or the background
+/// thread, are returned by the next `write` or `flush`. Data the inner writer
+/// did not take stays buffered and is retried by the following flush.
+pub struct CoalescingStream<W: Write + Send + 'static> {
+    shared: Arc<Shared<W>>,
+    threshold: usize,
+    flusher: Option<JoinHandle<()>>,
+}
+
+struct Shared<W> {
+    state: Mutex<State<W>>,
+    changed: Condvar,
+}
+
+struct State<W> {
+    inner: Option<W>,
+    buf: Vec<u8>,
+    first_buffered: Option<Instant>,
+    error: Option<io::Error>,
+    closed: bool,
+}
+
+impl<W> State<W>
+where
+    W: Write,
+{
+    // Only what the inner writer took leaves the buffer, so after an error
+    // the rest is still there for the next flush.
+    fn flush_buf(&mut self) -> io::Result<()> {
+        self.first_buffered = None;
+        let inner = match self.inner.as_mut() {
+            Some(inner) => inner,
+            None => {
+                self.buf.clear();
+                return Ok(());
+            }
+        };
+        while !self.buf.is_empty() {
+            match inner.write(&self.buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write the buffered data",
+                    ))
+                }
+                Ok(written) => {
+                    self.buf.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+    fn take_error(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> CoalescingStream<W> {
+    pub fn new(inner: W, threshold: usize, delay: Duration) -> CoalescingStream<W> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                inner: Some(inner),
+                buf: Vec::with_capacity(threshold),
+                first_buffered: None,
+                error: None,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let flusher = {
+            let shared = shared.clone();
+            thread::spawn(move || flush_after_delay(&shared, delay))
+        };
+        CoalescingStream {
+            shared,
+            threshold,
+            flusher: Some(flusher),
+        }
+    }
+    /// Bytes waiting to be written to the inner writer.
+    pub fn buffered(&self) -> usize {
+        self.lock().buf.len()
+    }
+    /// Flushes the buffer, stops the flush thread and returns the inner writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.stop()?;
+        Ok(self.lock().inner.take().unwrap())
+    }
+    fn lock(&self) -> MutexGuard<'_, State<W>> {
+        self.shared.state.lock().unwrap()
+    }
+    fn stop(&mut self) -> io::Result<()> {
+        {
+            let mut state = self.lock();
+            state.closed = true;
+            self.shared.changed.notify_all();
+        }
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+        let mut state = self.lock();
+        state.take_error()?;
+        state.flush_buf()
+    }
+}
+
+// Writes the buffer out once `delay` has passed since its first byte.
+fn flush_after_delay<W: Write>(shared: &Shared<W>, delay: Duration) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.closed {
+            return;
+        }
+        match state.first_buffered {
+            None => state = shared.changed.wait(state).unwrap(),
+            Some(first) => {
+                let deadline = first + delay;
+                let now = Instant::now();
+                if now < deadline {
+                    state = shared
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0;
+                } else if let Err(e) = state.flush_buf() {
+                    state.error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Write for CoalescingStream<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        state.take_error()?;
+        if state.first_buffered.is_none() {
+            state.first_buffered = Some(Instant::now());
+            self.shared.changed.notify_all();
+        }
+        state.buf.extend_from_slice(buf);
+        if state.buf.len() >= self.threshold {
+            // The bytes are taken either way, so a failure is kept for the next
+            // call, as for the flush thread.
+            if let Err(e) = state.flush_buf() {
+                state.error = Some(e);
+            }
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.lock();
+        state.take_error()?;
+        state.flush_buf()?;
+        match state.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for CoalescingStream<W> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{close_on_drop, close_on_err, error::UdtError, UdtBuilder, UdtSocket};
+
+use std::{
+    io::IoSliceMut,
+    mem,
+    net::{SocketAddr, ToSocketAddrs},
+};
+
+type Result<T> = std::result::Result<T, UdtError>;
+
+impl UdtBuilder {
+    /// Connects a message-mode socket to `remote`, trying each resolved address.
+    pub fn connect_datagram<A: ToSocketAddrs>(self, remote: A) -> Result<UdtDatagram> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
+        if let Ok(addrs) = remote.to_socket_addrs() {
+            for addr in addrs {
+                let socket = match addr {
+                    SocketAddr::V4(_) => UdtSocket::new_ipv4_dgram()?,
+                    SocketAddr::V6(_) => UdtSocket::new_ipv6_dgram()?,
+                };
+                match close_on_err(
+                    socket,
+                    self.config_socket(&socket)
+                        .and_then(|()| socket.connect(addr)),
+                ) {
+                    Ok(()) => return Ok(UdtDatagram { socket }),
+                    Err(e) => last_err = e,
+                }
+            }
+        }
+        Err(last_err)
+    }
+    /// Listens with a message-mode socket on the first of the resolved
+    /// addresses that binds.
+    pub fn listen_datagram<A: ToSocketAddrs>(
+        self,
+        addrs: A,
+        backlog: i32,
+    ) -> Result<UdtDatagramListener> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
+        if let Ok(addrs) = addrs.to_socket_addrs() {
+            for addr in addrs {
+                let socket = match addr {
+                    SocketAddr::V4(_) => UdtSocket::new_ipv4_dgram()?,
+                    SocketAddr::V6(_) => UdtSocket::new_ipv6_dgram()?,
+                };
+                match close_on_err(
+                    socket,
+                    self.config_socket(&socket)
+                        .and_then(|()| socket.bind(addr))
+                        .and_then(|socket| socket.listen(backlog)),
+                ) {
+                    Ok(()) => return Ok(UdtDatagramListener { socket }),
+                    Err(e) => last_err = e,
+                }
+            }
+        }
+        Err(last_err)
+    }
+}
+
+pub struct UdtDatagramListener {
+    socket: UdtSocket,
+}
+
+impl UdtDatagramListener {
+    pub fn accept(&self) -> Result<(UdtDatagram, SocketAddr)> {
+        let (socket, addr) = self.socket.accept()?;
+        Ok((UdtDatagram { socket }, addr))
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+    pub fn close(self) -> Result<()> {
+        // Skip our Drop, which would close the socket again.
+        mem::ManuallyDrop::new(self).socket.close()
+    }
+}
+
+impl Drop for UdtDatagramListener {
+    fn drop(&mut self) {
+        close_on_drop(self.socket);
+    }
+}
+
+/// A connection in UDT's message mode, where each `send` arrives as exactly one
+/// `recv`, reliably and in order.
+///
+/// Like the stream types, a datagram socket talks to the single peer it was
+/// connected to or accepted from.
+pub struct UdtDatagram {
+    socket: UdtSocket,
+}
+
+impl UdtDatagram {
+    /// Sends `msg` as one message, reliably and in order.
+    ///
+    /// Messages larger than the send buffer fail with `LargeMsg`, see
+    /// `max_message_size`.
+    pub fn send(&self, msg: &[u8]) -> Result<usize> {
+        self.socket.sendmsg(msg, -1, true)
+    }
+    /// Sends `msg` as one message that is dropped if it cannot be delivered
+    /// within `ttl` milliseconds (-1 for never). Without `in_order` it may be
+    /// delivered before messages sent earlier.
+    pub fn send_with(&self, msg: &[u8], ttl: i32, in_order: bool) -> Result<usize> {
+        self.socket.sendmsg(msg, ttl, in_order)
+    }
+    /// Receives exactly one message into `buf` and returns its length.
+    ///
+    /// A message longer than `buf` fails with `LargeMsg` instead of being
+    /// truncated. The message is consumed either way.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let msg = self.recv_at_most(buf.len())?;
+        buf[..msg.len()].copy_from_slice(&msg);
+        Ok(msg.len())
+    }
+    /// Receives exactly one message and scatters it across `bufs` in order,
+    /// e.g. a fixed-size header into one buffer and the payload into the next.
+    /// Returns the message length.
+    ///
+    /// Fails with `LargeMsg` like `recv` if the message is longer than all of
+    /// `bufs` together. UDT has no scatter receive of its own, so the message
+    /// is still copied once.
+    pub fn recv_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let msg = self.recv_at_most(bufs.iter().map(|buf| buf.len()).sum())?;
+        let mut rest = &msg[..];
+        for buf in bufs.iter_mut() {
+            let len = buf.len().min(rest.len());
+            buf[..len].copy_from_slice(&rest[..len]);
+            rest = &rest[len..];
+        }
+        Ok(msg.len())
+    }
+    // Receives one message of at most `max` bytes.
+    fn recv_at_most(&self, max: usize) -> Result<Vec<u8>> {
+        // One spare byte tells a message that exactly fits from a longer one.
+        let mut scratch = vec![0; max + 1];
+        let size = self.socket.recvmsg(&mut scratch)?;
+        if size > max {
+            return Err(UdtError::LargeMsg(format!(
+                "message does not fit in a buffer of {} bytes",
+                max
+            )));
+        }
+        scratch.truncate(size);
+        Ok(scratch)
+    }
+    /// Largest message `send` accepts, from the send buffer size and MSS.
+    /// Larger messages must be split by the application.
+    pub fn max_message_size(&self) -> Result<usize> {
+        self.socket.max_message_size()
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+    pub fn close(self) -> Result<()> {
+        // Skip our Drop, which would close the socket again.
+        mem::ManuallyDrop::new(self).socket.close()
+    }
+}
+
+impl Drop for UdtDatagram {
+    fn drop(&mut self) {
+        close_on_drop(self.socket);
+    }
+}
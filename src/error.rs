@@ -6,6 +6,7 @@ use std::{
     ffi::CStr,
     fmt::{self, Display, Formatter},
     io::{self, ErrorKind},
+    sync::Arc,
 };
 
 pub fn get_error<T>(ok: T) -> Result<T, UdtError> {
@@ -16,6 +17,13 @@ pub fn get_error<T>(ok: T) -> Result<T, UdtError> {
     }
 }
 
+/// An error reported by UDT, named after its UDT error code.
+///
+/// None of these mean an interrupted call, so none maps to
+/// `ErrorKind::Interrupted` and there is nothing to retry after a signal.
+/// UDT's blocking calls wait on condition variables, which signals do not
+/// interrupt, and its own threads do the UDP I/O: a `sendmsg` or `recvmsg`
+/// cut short by `EINTR` costs at most a packet, which UDT retransmits.
 #[derive(Clone, Debug)]
 pub enum UdtError {
     Success(String),
@@ -53,6 +61,9 @@ pub enum UdtError {
     AsyncRcv(String),
     Timeout(String),
     PeerErr(String),
+    /// An I/O error that did not come from UDT, e.g. from the reader passed to
+    /// `UdtSocket::send_from`. It is kept as this error's `source`.
+    Io(Arc<io::Error>),
 }
 
 impl From<i32> for UdtError {
@@ -98,9 +109,52 @@ impl From<i32> for UdtError {
     }
 }
 
-impl Display for UdtError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let msg = match self {
+impl UdtError {
+    /// UDT's numeric code for this error, e.g. 2001 for `ConnLost`. `Io` did
+    /// not come from UDT and reports -1, UDT's code for an unknown error.
+    pub fn code(&self) -> i32 {
+        match self {
+            UdtError::Success(_) => 0,
+            UdtError::ConnSetup(_) => 1000,
+            UdtError::NoServer(_) => 1001,
+            UdtError::ConnRej(_) => 1002,
+            UdtError::SockFail(_) => 1003,
+            UdtError::SecFail(_) => 1004,
+            UdtError::ConnFail(_) => 2000,
+            UdtError::ConnLost(_) => 2001,
+            UdtError::NoConn(_) => 2002,
+            UdtError::Resource(_) => 3000,
+            UdtError::Thread(_) => 3001,
+            UdtError::NoBuf(_) => 3002,
+            UdtError::File(_) => 4000,
+            UdtError::InvRdOff(_) => 4001,
+            UdtError::RdPerm(_) => 4002,
+            UdtError::InvWrOff(_) => 4003,
+            UdtError::WrPerm(_) => 4004,
+            UdtError::InvOp(_) => 5000,
+            UdtError::BoundSock(_) => 5001,
+            UdtError::ConnSock(_) => 5002,
+            UdtError::InvParam(_) => 5003,
+            UdtError::InvSock(_) => 5004,
+            UdtError::UnboundSock(_) => 5005,
+            UdtError::NoListen(_) => 5006,
+            UdtError::RdvNoServ(_) => 5007,
+            UdtError::RdvUnbound(_) => 5008,
+            UdtError::StreamIll(_) => 5009,
+            UdtError::DgramIll(_) => 5010,
+            UdtError::DupListen(_) => 5011,
+            UdtError::LargeMsg(_) => 5012,
+            UdtError::AsyncFail(_) => 6000,
+            UdtError::AsyncSnd(_) => 6001,
+            UdtError::AsyncRcv(_) => 6002,
+            UdtError::Timeout(_) => 6003,
+            UdtError::PeerErr(_) => 7000,
+            UdtError::Io(_) => -1,
+        }
+    }
+    /// The description alone, without the name and code `Display` adds.
+    pub fn message(&self) -> String {
+        match self {
             UdtError::Success(msg) => msg,
             UdtError::ConnSetup(msg) => msg,
             UdtError::NoServer(msg) => msg,
@@ -136,15 +190,84 @@ impl Display for UdtError {
             UdtError::AsyncRcv(msg) => msg,
             UdtError::Timeout(msg) => msg,
             UdtError::PeerErr(msg) => msg,
-        };
-        write!(f, "{}", msg)
+            UdtError::Io(e) => return e.to_string(),
+        }
+        .clone()
+    }
+    fn name(&self) -> &'static str {
+        match self {
+            UdtError::Success(_) => "Success",
+            UdtError::ConnSetup(_) => "ConnSetup",
+            UdtError::NoServer(_) => "NoServer",
+            UdtError::ConnRej(_) => "ConnRej",
+            UdtError::SockFail(_) => "SockFail",
+            UdtError::SecFail(_) => "SecFail",
+            UdtError::ConnFail(_) => "ConnFail",
+            UdtError::ConnLost(_) => "ConnLost",
+            UdtError::NoConn(_) => "NoConn",
+            UdtError::Resource(_) => "Resource",
+            UdtError::Thread(_) => "Thread",
+            UdtError::NoBuf(_) => "NoBuf",
+            UdtError::File(_) => "File",
+            UdtError::InvRdOff(_) => "InvRdOff",
+            UdtError::RdPerm(_) => "RdPerm",
+            UdtError::InvWrOff(_) => "InvWrOff",
+            UdtError::WrPerm(_) => "WrPerm",
+            UdtError::InvOp(_) => "InvOp",
+            UdtError::BoundSock(_) => "BoundSock",
+            UdtError::ConnSock(_) => "ConnSock",
+            UdtError::InvParam(_) => "InvParam",
+            UdtError::InvSock(_) => "InvSock",
+            UdtError::UnboundSock(_) => "UnboundSock",
+            UdtError::NoListen(_) => "NoListen",
+            UdtError::RdvNoServ(_) => "RdvNoServ",
+            UdtError::RdvUnbound(_) => "RdvUnbound",
+            UdtError::StreamIll(_) => "StreamIll",
+            UdtError::DgramIll(_) => "DgramIll",
+            UdtError::DupListen(_) => "DupListen",
+            UdtError::LargeMsg(_) => "LargeMsg",
+            UdtError::AsyncFail(_) => "AsyncFail",
+            UdtError::AsyncSnd(_) => "AsyncSnd",
+            UdtError::AsyncRcv(_) => "AsyncRcv",
+            UdtError::Timeout(_) => "Timeout",
+            UdtError::PeerErr(_) => "PeerErr",
+            UdtError::Io(_) => "Io",
+        }
     }
 }
 
-impl Error for UdtError {}
+// Formats as e.g. "ConnLost (2001): connection has been broken", so logs can be
+// traced back to the UDT error without looking up the message.
+impl Display for UdtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UdtError::Io(e) => write!(f, "Io: {}", e),
+            _ => write!(f, "{} ({}): {}", self.name(), self.code(), self.message()),
+        }
+    }
+}
+
+impl Error for UdtError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UdtError::Io(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
 
+// Callers decide whether to retry from these kinds, so keep them stable;
+// test_io_error_kinds pins the ones that matter for that.
 impl From<UdtError> for io::Error {
     fn from(e: UdtError) -> Self {
+        // Hand back the original error when nothing else shares it.
+        let e = match e {
+            UdtError::Io(inner) => match Arc::try_unwrap(inner) {
+                Ok(inner) => return inner,
+                Err(inner) => UdtError::Io(inner),
+            },
+            e => e,
+        };
         io::Error::new(
             match e {
                 UdtError::Success(_) => ErrorKind::Other,
@@ -182,17 +305,33 @@ impl From<UdtError> for io::Error {
                 UdtError::AsyncRcv(_) => ErrorKind::WouldBlock,
                 UdtError::Timeout(_) => ErrorKind::TimedOut,
                 UdtError::PeerErr(_) => ErrorKind::Other,
+                UdtError::Io(ref inner) => inner.kind(),
             },
             e,
         )
     }
 }
 
+// Recovers the UdtError embedded by the conversion above, so errors that went
+// through std::io keep their UDT variant. Any other error is kept whole as `Io`
+// rather than guessing a UDT variant from its kind.
+impl From<io::Error> for UdtError {
+    fn from(e: io::Error) -> Self {
+        let is_udt = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UdtError>())
+            .is_some();
+        if is_udt {
+            return *e.into_inner().unwrap().downcast::<UdtError>().unwrap();
+        }
+        UdtError::Io(Arc::new(e))
+    }
+}
+
 fn get_error_desc() -> String {
     unsafe {
         return CStr::from_ptr(udt_sys::udt_getlasterror_desc())
-            .to_str()
-            .unwrap()
-            .to_string();
+            .to_string_lossy()
+            .into_owned();
     };
 }
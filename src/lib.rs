@@ -1,38 +1,84 @@
+#[cfg(feature = "bytes")]
+mod bytes_io;
+mod coalescing;
+mod datagram;
 pub mod error;
+mod pool;
+mod rate_control;
+mod reactor;
+mod recording;
+mod selector;
+mod server;
 mod socket;
+mod timer;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+mod transport;
 
 use error::UdtError;
 use udt_sys;
 
 use futures::{
+    executor::ThreadPool,
     future::Future,
     io::{AsyncRead, AsyncWrite},
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
 
 use std::{
+    collections::VecDeque,
     io::{self, Read, Write},
+    mem,
     net::{SocketAddr, ToSocketAddrs},
     ops::Drop,
     os::raw::c_int,
     pin::Pin,
-    ptr, thread, time,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
 };
 
-pub use socket::{UdtSocket, UdtStatus};
+pub use coalescing::CoalescingStream;
+pub use datagram::{UdtDatagram, UdtDatagramListener};
+pub use pool::UdtSocketPool;
+pub use rate_control::RateControlConfig;
+#[cfg(feature = "debug-reactor")]
+pub use reactor::{reactor_debug, ReactorEntry};
+pub use recording::{IoOp, IoRecord, RecordingStream};
+pub use selector::{Interest, UdtSelector};
+pub use server::ServerHandle;
+pub use socket::{AddrFamily, PerfMon, Readiness, UdtSocket, UdtStatus, MIN_MSS};
+pub use transport::{Connection, Listener, TcpTransport, Transport};
 
 type Result<T> = std::result::Result<T, UdtError>;
 
+// Number of successful `startup` calls not yet matched by a `cleanup`.
+static STARTUP_COUNT: Mutex<usize> = Mutex::new(0);
+
+/// Initializes UDT. Safe to call repeatedly and from several threads; each call
+/// must be matched by a `cleanup`.
 pub fn startup() -> Result<()> {
-    let result = unsafe { udt_sys::udt_startup() };
-    if result == unsafe { udt_sys::UDT_ERROR } {
-        error::get_error(())
-    } else {
-        Ok(())
+    let mut count = STARTUP_COUNT.lock().unwrap();
+    if *count == 0 {
+        let result = unsafe { udt_sys::udt_startup() };
+        if result == unsafe { udt_sys::UDT_ERROR } {
+            error::get_error(())?;
+        }
     }
+    *count += 1;
+    Ok(())
 }
 
+/// Releases one `startup`. UDT is only torn down when the last user is gone.
 pub fn cleanup() -> Result<()> {
+    let mut count = STARTUP_COUNT.lock().unwrap();
+    release_startup(&mut count)?;
+    if *count > 0 {
+        return Ok(());
+    }
     let result = unsafe { udt_sys::udt_cleanup() };
     if result == unsafe { udt_sys::UDT_ERROR } {
         error::get_error(())
@@ -41,6 +87,98 @@ pub fn cleanup() -> Result<()> {
     }
 }
 
+/// Keeps UDT initialized for as long as it is alive.
+///
+/// Guards may be nested; UDT is only torn down once the last one is dropped.
+pub struct UdtRuntime {
+    _private: (),
+}
+
+impl UdtRuntime {
+    pub fn init() -> Result<Self> {
+        startup()?;
+        Ok(Self { _private: () })
+    }
+}
+
+impl Drop for UdtRuntime {
+    fn drop(&mut self) {
+        if let Err(_e) = cleanup() {
+            #[cfg(feature = "log")]
+            log::warn!("failed to clean up UDT: {}", _e);
+        }
+    }
+}
+
+fn release_startup(count: &mut usize) -> Result<()> {
+    *count = count
+        .checked_sub(1)
+        .ok_or_else(|| UdtError::InvOp("cleanup without startup".to_string()))?;
+    Ok(())
+}
+
+// For closes whose error cannot be returned, in Drop or while another error is
+// already being reported, so a failed close is only reported through `log`.
+fn close_on_drop(socket: UdtSocket) {
+    if let Err(_e) = socket.close() {
+        #[cfg(feature = "log")]
+        log::warn!("failed to close UDT socket {}: {}", socket.id, _e);
+    }
+}
+
+// Closes a socket no wrapper owns yet when setting it up fails, so builder
+// errors do not leak it.
+fn close_on_err<T>(socket: UdtSocket, result: Result<T>) -> Result<T> {
+    if result.is_err() {
+        close_on_drop(socket);
+    }
+    result
+}
+
+// Largest frame `UdtStream::recv_frame` accepts unless configured otherwise.
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+// Sent by both sides of `UdtStream::close_handshake`.
+const CLOSE_MARKER: &[u8; 8] = b"UDTCLOSE";
+
+// How often `UdtStream::flush_acked` checks the send buffer.
+const ACK_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+// Weight of each new sample in `UdtStream::smoothed_bandwidth`.
+const BANDWIDTH_SMOOTHING: f64 = 0.125;
+
+// One step of an exponentially weighted moving average. A NaN average means
+// there is none yet, so the first sample starts it.
+fn ewma(average: f64, sample: f64, weight: f64) -> f64 {
+    if average.is_nan() {
+        sample
+    } else {
+        average + weight * (sample - average)
+    }
+}
+
+// Size of the scratch buffer `UdtStream::skip` reads discarded data into.
+static DISCARD_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(64 * 1024);
+
+/// Sets the size of the scratch buffer used to discard data, 64 KiB by default.
+/// Larger buffers discard faster at the cost of memory per call.
+pub fn set_discard_buffer_size(bytes: usize) {
+    DISCARD_BUFFER_SIZE.store(bytes.max(1), Ordering::Relaxed);
+}
+
+// How long the reactor thread blocks in one epoll wait, and how often it looks
+// for waiters whose sockets were closed underneath them.
+static ASYNC_WAIT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(1000);
+
+/// Sets how long the reactor thread serving async streams and futures blocks
+/// in a single epoll wait, 1 second by default. About that often it also
+/// releases tasks waiting on sockets that were closed, so shorter timeouts
+/// notice those sooner at the cost of more wake-ups.
+pub fn set_async_wait_timeout(timeout: time::Duration) {
+    let millis = timeout.as_millis().clamp(1, i64::MAX as u128) as u64;
+    ASYNC_WAIT_TIMEOUT_MS.store(millis, Ordering::Relaxed);
+}
+
 pub fn builder() -> UdtBuilder {
     UdtBuilder {
         opt_vec: Vec::new(),
@@ -54,61 +192,855 @@ pub fn async_builder() -> UdtAsyncBuilder {
 
 pub struct UdtListener {
     socket: UdtSocket,
+    shared: Arc<SharedSocket>,
+}
+
+// Owns a socket shared between clones, closing it exactly once: on an explicit
+// close or when the last clone is dropped.
+struct SharedSocket {
+    socket: UdtSocket,
+    closed: AtomicBool,
+    // Connections accepted ahead of time by `UdtListener::pending`.
+    queued: Mutex<VecDeque<(UdtSocket, SocketAddr)>>,
+}
+
+impl SharedSocket {
+    fn new(socket: UdtSocket) -> Arc<SharedSocket> {
+        Arc::new(SharedSocket {
+            socket,
+            closed: AtomicBool::new(false),
+            queued: Mutex::new(VecDeque::new()),
+        })
+    }
+    fn accept(&self) -> Result<(UdtSocket, SocketAddr)> {
+        if let Some(queued) = self.queued.lock().unwrap().pop_front() {
+            return Ok(queued);
+        }
+        self.socket.accept()
+    }
+    // Accepts a connection from UDT's backlog only if one is already waiting.
+    // The listener's blocking mode is shared by every handle, so it is checked
+    // with a zero-timeout epoll wait instead of being switched. Should another
+    // handle take the connection in between, a blocking listener waits for the
+    // next one.
+    fn accept_waiting(&self) -> Result<Option<(UdtSocket, SocketAddr)>> {
+        let interest = udt_sys::EPOLLOpt::UDT_EPOLL_IN;
+        if !self.socket.poll_readiness(&interest)?.readable {
+            return Ok(None);
+        }
+        match self.socket.accept() {
+            Ok(accepted) => Ok(Some(accepted)),
+            Err(UdtError::AsyncRcv(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        for (socket, _addr) in self.queued.lock().unwrap().drain(..) {
+            close_on_drop(socket);
+        }
+        self.socket.close()
+    }
+}
+
+impl Drop for SharedSocket {
+    fn drop(&mut self) {
+        if !*self.closed.get_mut() {
+            for (socket, _addr) in self.queued.get_mut().unwrap().drain(..) {
+                close_on_drop(socket);
+            }
+            close_on_drop(self.socket);
+        }
+    }
 }
 
 impl UdtListener {
     pub fn accept(&self) -> Result<(UdtStream, SocketAddr)> {
-        let (socket, addr) = self.socket.accept()?;
-        Ok((UdtStream { socket }, addr))
+        let (socket, addr) = self.shared.accept()?;
+        Ok((UdtStream::new(socket), addr))
+    }
+    /// Accepts the next connection whose peer address passes `filter`.
+    ///
+    /// Rejected connections are torn down right away, discarding anything
+    /// they sent, before a stream is ever built for them.
+    pub fn accept_with_filter<F>(&self, filter: F) -> Result<(UdtStream, SocketAddr)>
+    where
+        F: Fn(SocketAddr) -> bool,
+    {
+        loop {
+            let (socket, addr) = self.shared.accept()?;
+            if filter(addr) {
+                return Ok((UdtStream::new(socket), addr));
+            }
+            let _ = socket.set_linger(0);
+            close_on_drop(socket);
+        }
+    }
+    /// Accepts up to `max` pending connections without blocking.
+    ///
+    /// Returns as soon as the backlog is empty, so the result may be empty too.
+    /// Connections accepted before an error are still returned; the error is
+    /// only reported if none were.
+    pub fn accept_many(&self, max: usize) -> Result<Vec<(UdtStream, SocketAddr)>> {
+        let mut accepted = Vec::new();
+        let mut error = None;
+        while accepted.len() < max {
+            let queued = self.shared.queued.lock().unwrap().pop_front();
+            let next = match queued {
+                Some(queued) => Ok(Some(queued)),
+                None => self.shared.accept_waiting(),
+            };
+            match next {
+                Ok(Some((socket, addr))) => accepted.push((UdtStream::new(socket), addr)),
+                Ok(None) => break,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+        match error {
+            Some(e) if accepted.is_empty() => Err(e),
+            _ => Ok(accepted),
+        }
+    }
+    /// Switches `accept` between blocking and non-blocking mode. A non-blocking
+    /// `accept` fails with `AsyncRcv` when nothing is pending, and the streams it
+    /// returns are non-blocking too; `try_accept` avoids both.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.socket.set_rcvsyn(!nonblocking)
+    }
+    /// Accepts a pending connection, or returns `Ok(None)` if there is none.
+    ///
+    /// This only returns without waiting once the listener is non-blocking,
+    /// see `set_nonblocking`. The returned stream is blocking either way.
+    pub fn try_accept(&self) -> Result<Option<(UdtStream, SocketAddr)>> {
+        match self.shared.accept() {
+            Ok((socket, addr)) => {
+                if let Err(e) = socket.set_rcvsyn(true) {
+                    close_on_drop(socket);
+                    return Err(e);
+                }
+                Ok(Some((UdtStream::new(socket), addr)))
+            }
+            Err(UdtError::AsyncRcv(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Accepts one connection and hands it to `handler` on `pool`.
+    ///
+    /// The stream is moved into the worker, so it is closed there once `handler` returns.
+    pub fn accept_into<F>(&self, pool: &ThreadPool, handler: F) -> Result<SocketAddr>
+    where
+        F: FnOnce(UdtStream, SocketAddr) + Send + 'static,
+    {
+        let (stream, addr) = self.accept()?;
+        pool.spawn_ok(async move { handler(stream, addr) });
+        Ok(addr)
+    }
+    /// Number of connections waiting to be accepted.
+    ///
+    /// UDT cannot report the size of its backlog, so this accepts everything
+    /// pending without blocking and holds it for the following accepts, which
+    /// return these connections first, in order.
+    pub fn pending(&self) -> Result<usize> {
+        let mut queued = self.shared.queued.lock().unwrap();
+        while let Some(accepted) = self.shared.accept_waiting()? {
+            queued.push_back(accepted);
+        }
+        Ok(queued.len())
     }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
     }
+    /// Another handle to the same listening socket, e.g. to accept on several
+    /// threads at once. The socket is closed when the last handle is dropped.
+    pub fn try_clone(&self) -> Result<UdtListener> {
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Err(UdtError::InvSock("listener already closed".to_string()));
+        }
+        Ok(UdtListener {
+            socket: self.socket,
+            shared: self.shared.clone(),
+        })
+    }
+    /// Closes the listening socket for every handle, waking their pending accepts.
+    pub fn close(self) -> Result<()> {
+        self.shared.close()
+    }
+    /// Like `close`, but through a shared reference, e.g. from a thread other
+    /// than the one accepting. UDT wakes every `accept` blocked on the socket,
+    /// which then fails, as does any later `accept` on any handle.
+    pub fn shutdown(&self) -> Result<()> {
+        self.shared.close()
+    }
 }
 
-impl Drop for UdtListener {
-    fn drop(&mut self) {
-        if let Err(_) = self.socket.close() {}
+/// What a `UdtStream` does with unsent data when it is dropped without an explicit close.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DropPolicy {
+    /// Close honoring the socket's linger setting.
+    #[default]
+    Linger,
+    /// Close immediately, discarding any unsent data.
+    Abort,
+}
+
+/// Snapshot of the options UDT settled on for a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EffectiveConfig {
+    pub mss: i32,
+    pub flight_flag_size: i32,
+    pub snd_buf: i32,
+    pub rcv_buf: i32,
+    pub udp_snd_buf: i32,
+    pub udp_rcv_buf: i32,
+    pub linger: i32,
+    pub rendezvous: bool,
+    pub snd_timeout: i32,
+    pub rcv_timeout: i32,
+    pub reuse_addr: bool,
+    pub max_bw: i64,
+}
+
+impl EffectiveConfig {
+    fn read(socket: &UdtSocket) -> Result<EffectiveConfig> {
+        Ok(EffectiveConfig {
+            mss: socket.get_mss()?,
+            flight_flag_size: socket.get_fc()?,
+            snd_buf: socket.get_sndbuf()?,
+            rcv_buf: socket.get_rcvbuf()?,
+            udp_snd_buf: socket.get_udp_sndbuf()?,
+            udp_rcv_buf: socket.get_udp_rcvbuf()?,
+            linger: socket.get_linger()?,
+            rendezvous: socket.get_rendezvous()?,
+            snd_timeout: socket.get_sndtimeo()?,
+            rcv_timeout: socket.get_rcvtimeo()?,
+            reuse_addr: socket.get_reuseaddr()?,
+            max_bw: socket.get_maxbw()?,
+        })
     }
 }
 
+/// Snapshot of a connection's addresses, state and rate settings, see `UdtStream::info`.
+#[derive(Copy, Clone, Debug)]
+pub struct ConnectionInfo {
+    pub local_addr: SocketAddr,
+    pub peer_addr: SocketAddr,
+    pub state: UdtStatus,
+    pub mss: i32,
+    pub max_bw: i64,
+}
+
+#[derive(Debug)]
 pub struct UdtStream {
     socket: UdtSocket,
+    drop_policy: DropPolicy,
+    peak_send: usize,
+    peak_recv: usize,
+    max_frame_size: usize,
+    last_io: time::Instant,
+    bytes_sent: u64,
+    bytes_received: u64,
+    // Bits of the f64 average behind `smoothed_bandwidth`, NaN until sampled.
+    bandwidth: AtomicU64,
+    // Data from `write_buffered` that did not fit in the send buffer yet.
+    write_queue: Vec<u8>,
+    // Cached so the peer stays known after the connection breaks.
+    peer_addr: Option<SocketAddr>,
+    // For connections run by `serve`: set by whichever of the stream and
+    // `ServerHandle::shutdown` closes the socket first, so only one of them does.
+    closed: Option<Arc<AtomicBool>>,
 }
 
 impl UdtStream {
+    fn new(socket: UdtSocket) -> Self {
+        Self {
+            socket,
+            drop_policy: DropPolicy::default(),
+            peak_send: 0,
+            peak_recv: 0,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            last_io: time::Instant::now(),
+            bytes_sent: 0,
+            bytes_received: 0,
+            bandwidth: AtomicU64::new(f64::NAN.to_bits()),
+            write_queue: Vec::new(),
+            peer_addr: socket.peer_addr().ok(),
+            closed: None,
+        }
+    }
+    // All reads and writes on the stream go through recv and send for bookkeeping.
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.sample_occupancy();
+        let received = self.socket.recv(buf)?;
+        self.last_io = time::Instant::now();
+        self.bytes_received += received as u64;
+        Ok(received)
+    }
+    fn send(&mut self, buf: &[u8]) -> Result<usize> {
+        // Queued data goes out first to keep the byte order.
+        self.flush_queue()?;
+        let sent = self.socket.send(buf)?;
+        self.last_io = time::Instant::now();
+        self.bytes_sent += sent as u64;
+        self.sample_occupancy();
+        Ok(sent)
+    }
+    // Sends up to `max` queued bytes, blocking if the send buffer fills.
+    fn send_queued(&mut self, max: usize) -> Result<usize> {
+        let len = max.min(self.write_queue.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let sent = self.socket.send(&self.write_queue[..len])?;
+        self.write_queue.drain(..sent);
+        self.last_io = time::Instant::now();
+        self.bytes_sent += sent as u64;
+        self.sample_occupancy();
+        Ok(sent)
+    }
+    fn flush_queue(&mut self) -> Result<()> {
+        while !self.write_queue.is_empty() {
+            self.send_queued(usize::MAX)?;
+        }
+        Ok(())
+    }
+    fn send_exact(&mut self, buf: &[u8]) -> Result<()> {
+        let mut sent = 0;
+        while sent < buf.len() {
+            sent += self.send(&buf[sent..])?;
+        }
+        Ok(())
+    }
+    fn recv_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut received = 0;
+        while received < buf.len() {
+            received += self.recv(&mut buf[received..])?;
+        }
+        Ok(())
+    }
+    fn sample_occupancy(&mut self) {
+        if let Ok(packets) = self.socket.get_snddata() {
+            self.peak_send = self.peak_send.max(packets as usize);
+        }
+        if let Ok(packets) = self.socket.get_rcvdata() {
+            self.peak_recv = self.peak_recv.max(packets as usize);
+        }
+    }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
     }
+    /// The peer's address, as recorded when the connection was set up, so it
+    /// is still available once the connection has broken.
     pub fn peer_addr(&self) -> Result<SocketAddr> {
-        self.socket.peer_addr()
+        match self.peer_addr {
+            Some(addr) => Ok(addr),
+            None => self.socket.peer_addr(),
+        }
+    }
+    // Gives up the socket without running our Drop, so explicit closes and
+    // `into_raw` do not close it a second time.
+    fn into_socket(mut self) -> UdtSocket {
+        self.write_queue = Vec::new();
+        let this = mem::ManuallyDrop::new(self);
+        this.socket
+    }
+    // False once `ServerHandle::shutdown` has aborted the connection, which
+    // leaves nothing for the stream to close.
+    fn claim_close(&self) -> bool {
+        match &self.closed {
+            Some(closed) => !closed.swap(true, Ordering::SeqCst),
+            None => true,
+        }
+    }
+    fn close_socket(self) -> Result<()> {
+        let owned = self.claim_close();
+        let socket = self.into_socket();
+        if owned {
+            socket.close()
+        } else {
+            Ok(())
+        }
     }
     pub fn close(self) -> Result<()> {
-        self.socket.close()
+        self.close_socket()
+    }
+    /// Closes the stream, lingering up to `linger` seconds for unsent data
+    /// regardless of the socket's linger setting.
+    pub fn close_gracefully(self, linger: i32) -> Result<()> {
+        self.socket.set_linger(linger)?;
+        self.close_socket()
+    }
+    /// Closes the stream immediately, discarding unsent data, like a TCP
+    /// reset. Whatever the linger setting, this never blocks.
+    pub fn abort(self) -> Result<()> {
+        self.socket.set_linger(0)?;
+        self.close_socket()
+    }
+    /// Closes the stream, waiting at most `timeout` for unsent data to drain
+    /// before discarding it. UDT lingers in whole seconds, so the bound is
+    /// rounded up to the next second.
+    pub fn close_timeout(self, timeout: time::Duration) -> Result<()> {
+        self.close_gracefully(socket::linger_secs(Some(timeout)))
+    }
+    /// Closes the stream once both sides agree they are done, like a mutual TCP FIN.
+    ///
+    /// Each side sends the 8-byte marker `UDTCLOSE` and then waits up to
+    /// `timeout` for the peer's marker before closing. By then everything either
+    /// side wrote before its marker has reached the other, so no in-flight data
+    /// is discarded. The peer must call `close_handshake` too, after reading all
+    /// application data; anything other than the marker fails with `PeerErr`.
+    pub fn close_handshake(mut self, timeout: time::Duration) -> Result<()> {
+        let deadline = time::Instant::now() + timeout;
+        self.send_exact(CLOSE_MARKER)?;
+        let mut marker = [0; CLOSE_MARKER.len()];
+        let mut received = 0;
+        while received < marker.len() {
+            let left = deadline.saturating_duration_since(time::Instant::now());
+            match self.socket.recv_timeout(&mut marker[received..], left)? {
+                Some(size) => received += size,
+                None => {
+                    return Err(UdtError::Timeout(
+                        "peer did not finish the close handshake".to_string(),
+                    ))
+                }
+            }
+        }
+        if &marker != CLOSE_MARKER {
+            return Err(UdtError::PeerErr(
+                "unexpected data in place of the close marker".to_string(),
+            ));
+        }
+        self.close_socket()
+    }
+    /// Sets how the stream is closed when dropped. Explicit closes are not affected.
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.drop_policy = policy;
+    }
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+    /// Gives up ownership of the socket and returns its UDT id, e.g. to hand the
+    /// connection to C code that uses UDT directly. The socket is left open:
+    /// whoever holds the id must close it exactly once, with `udt_close` or by
+    /// passing it back to `from_raw`. Data still queued by `write_buffered` is
+    /// discarded, so `flush` first.
+    pub fn into_raw(self) -> i32 {
+        self.claim_close();
+        self.into_socket().id
+    }
+    /// Takes ownership of a connected socket by its UDT id, e.g. one from
+    /// `into_raw`. The stream closes it when dropped, so nothing else may close
+    /// it afterwards.
+    pub fn from_raw(id: i32) -> UdtStream {
+        UdtStream::new(UdtSocket::from_raw(id))
+    }
+    /// The underlying UDT socket, e.g. to register it with an external UDT epoll set.
+    ///
+    /// This is an advanced escape hatch: the stream still owns the socket, so it must
+    /// not be closed through the returned handle.
+    pub fn as_udt_socket(&self) -> UdtSocket {
+        self.socket
+    }
+    /// Bytes of user data that fit in one UDT data packet at the current MSS.
+    pub fn packet_payload_size(&self) -> Result<usize> {
+        // The MSS covers the IP and UDP headers (28 bytes) and the UDT header (16 bytes).
+        Ok((self.socket.get_mss()? - 44).max(1) as usize)
+    }
+    /// Wraps the stream in a `BufWriter` holding one packet of payload, so small
+    /// writes are batched into full-sized sends. The buffer is flushed on drop.
+    ///
+    /// For other sizes use `BufWriter::with_capacity` directly; reads can be
+    /// batched the same way with `std::io::BufReader`.
+    pub fn into_buf_writer(self) -> Result<io::BufWriter<UdtStream>> {
+        let capacity = self.packet_payload_size()?;
+        Ok(io::BufWriter::with_capacity(capacity, self))
+    }
+    /// Takes all of `buf` without blocking on a full send buffer.
+    ///
+    /// Only as much as the send buffer has room for is handed to UDT; the rest
+    /// is queued in the stream and sent by later calls to `write_buffered` or
+    /// `drain_buffered`, or by `flush`. Watch `buffered_len` to apply
+    /// backpressure. Queued data is discarded if the stream is dropped or
+    /// closed without flushing.
+    pub fn write_buffered(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write_queue.extend_from_slice(buf);
+        self.drain_buffered()?;
+        Ok(buf.len())
+    }
+    /// Sends as much queued data as the send buffer has room for, without
+    /// blocking, and returns how much was sent.
+    pub fn drain_buffered(&mut self) -> Result<usize> {
+        let room = self.socket.snd_buf_room()?;
+        self.send_queued(room)
+    }
+    /// Bytes queued by `write_buffered` that have not been handed to UDT yet.
+    pub fn buffered_len(&self) -> usize {
+        self.write_queue.len()
+    }
+    /// Waits up to `timeout` until the peer has acknowledged everything written
+    /// so far, including data queued by `write_buffered`.
+    ///
+    /// UDT keeps sent data in the send buffer until the peer acknowledges it,
+    /// so an empty send buffer means every byte reached the peer's UDT. It does
+    /// not mean the peer application has read it. Fails with `Timeout` if data
+    /// is still unacknowledged at the deadline, or `ConnLost` if the
+    /// connection breaks first.
+    pub fn flush_acked(&mut self, timeout: time::Duration) -> Result<()> {
+        let deadline = time::Instant::now() + timeout;
+        self.flush_queue()?;
+        while self.socket.get_snddata()? > 0 {
+            if !matches!(self.socket.get_state(), UdtStatus::Connected) {
+                return Err(UdtError::ConnLost(
+                    "connection lost before data was acknowledged".to_string(),
+                ));
+            }
+            let now = time::Instant::now();
+            if now >= deadline {
+                return Err(UdtError::Timeout(
+                    "sent data was not acknowledged in time".to_string(),
+                ));
+            }
+            thread::sleep(ACK_POLL_INTERVAL.min(deadline - now));
+        }
+        Ok(())
+    }
+    /// Reads up to `max` bytes into a new `Vec` sized to what was received.
+    ///
+    /// The packets already buffered, at a full payload each, are used as an
+    /// allocation hint. Once the connection is gone this fails with `ConnLost`,
+    /// as `recv` does, whether the peer closed or the link broke.
+    pub fn recv_to_vec(&mut self, max: usize) -> Result<Vec<u8>> {
+        let buffered = self.socket.get_rcvdata()? as usize * self.packet_payload_size()?;
+        let hint = if buffered > 0 { buffered } else { 64 * 1024 };
+        let mut buf = vec![0; hint.min(max)];
+        if buf.is_empty() {
+            return Ok(buf);
+        }
+        let size = self.recv(&mut buf)?;
+        buf.truncate(size);
+        buf.shrink_to_fit();
+        Ok(buf)
+    }
+    /// Reads and discards up to `len` bytes, returning how many were discarded.
+    ///
+    /// The scratch buffer size is set crate-wide with `set_discard_buffer_size`.
+    pub fn skip(&mut self, len: u64) -> Result<u64> {
+        let size = DISCARD_BUFFER_SIZE.load(Ordering::Relaxed) as u64;
+        let mut scratch = vec![0; len.min(size) as usize];
+        let mut skipped = 0;
+        while skipped < len {
+            let want = (len - skipped).min(scratch.len() as u64) as usize;
+            let received = self.recv(&mut scratch[..want])?;
+            if received == 0 {
+                break;
+            }
+            skipped += received as u64;
+        }
+        Ok(skipped)
+    }
+    /// Whether `len` more bytes fit in the send buffer right now.
+    pub fn can_send(&self, len: usize) -> Result<bool> {
+        Ok(len <= self.socket.snd_buf_room()?)
+    }
+    /// Queues the whole of `buf` or nothing at all.
+    ///
+    /// Returns `Ok(false)` without sending anything if `buf` does not fit in the
+    /// send buffer.
+    pub fn try_send_all(&mut self, buf: &[u8]) -> Result<bool> {
+        if !self.can_send(buf.len())? {
+            return Ok(false);
+        }
+        self.send_exact(buf)?;
+        Ok(true)
+    }
+}
+
+//Framing
+impl UdtStream {
+    /// Sends `frame` prefixed with its length as a big-endian u32.
+    pub fn send_frame(&mut self, frame: &[u8]) -> Result<()> {
+        if frame.len() > self.max_frame_size || frame.len() > u32::MAX as usize {
+            return Err(UdtError::LargeMsg(format!(
+                "frame of {} bytes exceeds the maximum frame size",
+                frame.len()
+            )));
+        }
+        self.send_exact(&(frame.len() as u32).to_be_bytes())?;
+        self.send_exact(frame)
+    }
+    /// Receives one frame written by `send_frame`.
+    ///
+    /// A declared length above `max_frame_size` fails with `LargeMsg` before
+    /// anything is allocated. The frame body is left unread, so the stream
+    /// should be closed afterwards.
+    pub fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        let mut header = [0; 4];
+        self.recv_exact(&mut header)?;
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.max_frame_size {
+            return Err(UdtError::LargeMsg(format!(
+                "peer declared a frame of {} bytes, the maximum is {}",
+                len, self.max_frame_size
+            )));
+        }
+        let mut frame = vec![0; len];
+        self.recv_exact(&mut frame)?;
+        Ok(frame)
+    }
+    /// Sets the largest frame `send_frame` and `recv_frame` accept, 16 MiB by default.
+    pub fn set_max_frame_size(&mut self, bytes: usize) {
+        self.max_frame_size = bytes;
+    }
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+}
+
+//Live socket options
+impl UdtStream {
+    pub fn set_max_bw(&self, val: i64) -> Result<()> {
+        self.socket.set_maxbw(val)
+    }
+    pub fn get_max_bw(&self) -> Result<i64> {
+        self.socket.get_maxbw()
+    }
+    pub fn set_snd_timeout(&self, val: i32) -> Result<()> {
+        self.socket.set_sndtimeo(val)
+    }
+    pub fn set_rcv_timeout(&self, val: i32) -> Result<()> {
+        self.socket.set_rcvtimeo(val)
+    }
+    /// Switches sends and receives between blocking and non-blocking mode.
+    ///
+    /// In non-blocking mode, reads and writes that cannot make progress fail
+    /// with `ErrorKind::WouldBlock` instead of waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.socket.set_sndsyn(!nonblocking)?;
+        self.socket.set_rcvsyn(!nonblocking)
+    }
+    /// Maximum flow window, in packets, in effect for this connection.
+    ///
+    /// Read after connect, this is the window UDT uses to limit packets in
+    /// flight, which may be smaller than what the peer configured.
+    pub fn negotiated_flow_window(&self) -> Result<i32> {
+        self.socket.get_fc()
+    }
+    /// Packets still waiting in the UDT send buffer, unsent or unacknowledged.
+    /// UDT counts its buffers in packets, not bytes.
+    pub fn packets_buffered_to_send(&self) -> Result<usize> {
+        Ok(self.socket.get_snddata()? as usize)
+    }
+    /// Packets received and ready to be read without blocking. The last one
+    /// may be partly read already.
+    pub fn packets_available_to_read(&self) -> Result<usize> {
+        Ok(self.socket.get_rcvdata()? as usize)
+    }
+    /// Whether a read would return data right away. A stream closed by the
+    /// peer reports `false` once drained, although a read then returns EOF.
+    pub fn peek_ready(&self) -> Result<bool> {
+        Ok(self.socket.get_rcvdata()? > 0)
+    }
+    /// Highest send buffer occupancy, in packets, seen by this stream's reads and writes.
+    pub fn peak_send_occupancy(&self) -> usize {
+        self.peak_send
+    }
+    /// Highest receive buffer occupancy, in packets, seen by this stream's reads and writes.
+    pub fn peak_recv_occupancy(&self) -> usize {
+        self.peak_recv
+    }
+    pub fn reset_peaks(&mut self) {
+        self.peak_send = 0;
+        self.peak_recv = 0;
+    }
+    /// Whether the connection is still up, without doing any I/O.
+    ///
+    /// UDT exchanges keep-alive packets on idle connections and marks a
+    /// connection broken once the peer stops answering, so an idle stream can be
+    /// health-checked here long before a write would fail. A zero-byte send
+    /// would not probe anything extra: UDT does not put it on the wire.
+    pub fn check_alive(&self) -> Result<bool> {
+        if let UdtStatus::Connected = self.socket.get_state() {
+            let event = self.socket.get_event()?;
+            Ok(event & udt_sys::EPOLLOpt::UDT_EPOLL_ERR != udt_sys::EPOLLOpt::UDT_EPOLL_ERR)
+        } else {
+            Ok(false)
+        }
+    }
+    /// Time since the last successful read or write, or since the stream was
+    /// created if there has been none.
+    pub fn idle_duration(&self) -> time::Duration {
+        self.last_io.elapsed()
+    }
+    /// Total bytes written through this stream since it was created. Unlike
+    /// UDT's performance counters, this never resets.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+    /// Total bytes read through this stream since it was created.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+    /// UDT's performance counters for this connection, see `PerfMon`.
+    pub fn perfmon(&self, clear: bool) -> Result<PerfMon> {
+        self.socket.perfmon(clear)
+    }
+    /// Estimated bottleneck bandwidth in Mbit/s, smoothed across calls.
+    ///
+    /// Each call takes a fresh `mbps_bandwidth` sample from perfmon and moves
+    /// an exponentially weighted average an eighth of the way towards it, the
+    /// factor TCP uses for its smoothed RTT. After a step change the average
+    /// has covered 1 - 0.875^n of it by the nth call, about 88% after 16. Call
+    /// it at a steady pace for an average over time.
+    pub fn smoothed_bandwidth(&self) -> Result<f64> {
+        let sample = self.socket.perfmon(false)?.mbps_bandwidth;
+        let mut average = sample;
+        let _ = self
+            .bandwidth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                average = ewma(f64::from_bits(bits), sample, BANDWIDTH_SMOOTHING);
+                Some(average.to_bits())
+            });
+        Ok(average)
+    }
+    /// Adapts the `set_maxbw` limit to the loss this connection sees.
+    ///
+    /// A background thread checks perfmon's send loss counter every
+    /// `config.interval`, cutting the limit on loss and raising it otherwise,
+    /// see `RateControlConfig`. The thread ends once the connection is no
+    /// longer connected. It keeps overriding the limit until then, so a later
+    /// `set_maxbw` call only holds until the next check.
+    pub fn enable_loss_based_rate_control(&self, config: RateControlConfig) -> Result<()> {
+        config.validate().map_err(UdtError::InvParam)?;
+        let socket = self.socket;
+        let lost_total = socket.perfmon(false)?.pkt_snd_loss_total;
+        socket.set_maxbw(config.max_bw)?;
+        let mut controller = rate_control::RateController::new(config, lost_total.into());
+        thread::spawn(move || loop {
+            thread::sleep(config.interval);
+            if !matches!(socket.get_state(), UdtStatus::Connected) {
+                break;
+            }
+            let lost_total = match socket.perfmon(false) {
+                Ok(perf) => perf.pkt_snd_loss_total,
+                Err(_) => break,
+            };
+            if socket
+                .set_maxbw(controller.update(lost_total.into()))
+                .is_err()
+            {
+                break;
+            }
+        });
+        Ok(())
+    }
+}
+
+//Socket option getters
+impl UdtStream {
+    /// Maximum packet size. After connect this is the value negotiated with
+    /// the peer, the smaller of the two sides' settings.
+    pub fn get_mss(&self) -> Result<i32> {
+        self.socket.get_mss()
+    }
+    /// Maximum number of packets in flight (`UDT_FC`).
+    pub fn get_flight_flag_size(&self) -> Result<i32> {
+        self.socket.get_fc()
+    }
+    /// All options as UDT currently reports them.
+    pub fn effective_config(&self) -> Result<EffectiveConfig> {
+        EffectiveConfig::read(&self.socket)
+    }
+    /// Addresses, state, negotiated MSS and bandwidth cap in one snapshot.
+    pub fn info(&self) -> Result<ConnectionInfo> {
+        // The state goes first, so a connection breaking part way through is
+        // reported as broken rather than with a mix of before and after.
+        let state = self.socket.get_state();
+        Ok(ConnectionInfo {
+            local_addr: self.local_addr()?,
+            peer_addr: self.peer_addr()?,
+            state,
+            mss: self.socket.get_mss()?,
+            max_bw: self.socket.get_maxbw()?,
+        })
+    }
+    pub fn get_fc(&self) -> Result<i32> {
+        self.socket.get_fc()
+    }
+    pub fn get_snd_buf(&self) -> Result<i32> {
+        self.socket.get_sndbuf()
+    }
+    pub fn get_rcv_buf(&self) -> Result<i32> {
+        self.socket.get_rcvbuf()
+    }
+    pub fn get_udp_snd_buf(&self) -> Result<i32> {
+        self.socket.get_udp_sndbuf()
+    }
+    pub fn get_udp_rcv_buf(&self) -> Result<i32> {
+        self.socket.get_udp_rcvbuf()
+    }
+    pub fn get_linger(&self) -> Result<i32> {
+        self.socket.get_linger()
+    }
+    pub fn is_rendezvous(&self) -> Result<bool> {
+        self.socket.get_rendezvous()
+    }
+    pub fn get_snd_timeout(&self) -> Result<i32> {
+        self.socket.get_sndtimeo()
+    }
+    pub fn get_rcv_timeout(&self) -> Result<i32> {
+        self.socket.get_rcvtimeo()
+    }
+    pub fn get_reuse_addr(&self) -> Result<bool> {
+        self.socket.get_reuseaddr()
+    }
+    pub fn get_state(&self) -> UdtStatus {
+        self.socket.get_state()
     }
 }
 
+/// Once the connection is gone and everything buffered has been read, reads
+/// fail with `ConnLost`, mapped to `ErrorKind::ConnectionAborted`. UDT reports
+/// a peer close and a broken link the same way, so the stream cannot tell a
+/// complete transfer from a truncated one and never returns `Ok(0)` for it.
+/// Applications that need a clean close should agree on it themselves, e.g.
+/// with a length prefix, `close_handshake` or `flush_acked`.
 impl Read for UdtStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        Ok(self.socket.recv(buf)?)
+        Ok(UdtStream::recv(self, buf)?)
     }
 }
 
 impl Write for UdtStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Ok(self.socket.send(buf)?)
+        Ok(UdtStream::send(self, buf)?)
     }
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        Ok(self.flush_queue()?)
     }
 }
 
 impl Drop for UdtStream {
     fn drop(&mut self) {
-        if let Err(_) = self.socket.close() {}
+        if !self.claim_close() {
+            return;
+        }
+        if let DropPolicy::Abort = self.drop_policy {
+            let _ = self.socket.set_linger(0);
+        }
+        close_on_drop(self.socket);
     }
 }
 
+/// A socket bound to a local address but not yet connected, from `UdtBuilder::bind`.
+///
+/// Connecting it makes the outgoing connection originate from that address,
+/// e.g. to pick the interface on a multi-homed host or to use a fixed local port.
 pub struct UdtBoundSocket {
     socket: UdtSocket,
 }
@@ -116,9 +1048,7 @@ pub struct UdtBoundSocket {
 impl UdtBoundSocket {
     pub fn connect<A: ToSocketAddrs>(self, remote: A) -> Result<UdtStream> {
         self.socket.connect(remote)?;
-        Ok(UdtStream {
-            socket: self.socket,
-        })
+        Ok(UdtStream::new(self.socket))
     }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
@@ -130,56 +1060,116 @@ pub struct UdtBuilder {
 }
 
 impl UdtBuilder {
+    /// Binds to `local` without connecting. Pass an interface address to choose
+    /// where outgoing packets leave from, and a non-zero port to fix the local
+    /// port; port 0 lets the system pick one. Then call `connect` on the result:
+    ///
+    /// ```no_run
+    /// let stream = udt_rs::builder()
+    ///     .bind("192.168.1.10:9000")?
+    ///     .connect("192.168.1.20:9000")?;
+    /// # Ok::<(), udt_rs::error::UdtError>(())
+    /// ```
+    ///
+    /// Each resolved address is tried in turn until one binds, like
+    /// `TcpListener::bind`; fails with the error of the last attempt.
     pub fn bind<A: ToSocketAddrs>(self, local: A) -> Result<UdtBoundSocket> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
         if let Ok(addrs) = local.to_socket_addrs() {
             for addr in addrs {
-                let socket = match addr {
-                    SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
-                    SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
-                };
-                self.config_socket(&socket)?;
-                let socket = socket.bind(addr)?;
-                return Ok(UdtBoundSocket { socket });
+                match self.bind_addr(addr) {
+                    Ok(socket) => return Ok(UdtBoundSocket { socket }),
+                    Err(e) => last_err = e,
+                }
             }
         }
-        Err(UdtError::SockFail("invalid address".to_string()))
+        Err(last_err)
     }
+    /// Connects to the first of the resolved addresses that accepts, creating a
+    /// socket of the matching family for each attempt. Fails with the error of
+    /// the last attempt.
     pub fn connect<A: ToSocketAddrs>(self, remote: A) -> Result<UdtStream> {
-        if let Ok(addrs) = remote.to_socket_addrs() {
-            for addr in addrs {
-                let socket = match addr {
-                    SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
-                    SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
-                };
-                self.config_socket(&socket)?;
-                socket.connect(remote)?;
-                return Ok(UdtStream { socket });
+        self.connect_any(&remote)
+    }
+    /// Like `connect`, but makes up to `attempts` attempts, e.g. while the
+    /// server is still starting. Waits `backoff` after the first failure and
+    /// twice as long after each one after that. `remote` is resolved again for
+    /// every attempt. Fails with the error of the last attempt.
+    pub fn connect_retry<A: ToSocketAddrs>(
+        self,
+        remote: A,
+        attempts: u32,
+        backoff: time::Duration,
+    ) -> Result<UdtStream> {
+        let mut delay = backoff;
+        let mut attempt = 1;
+        loop {
+            match self.connect_any(&remote) {
+                Ok(stream) => return Ok(stream),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(_) => {}
             }
+            thread::sleep(delay);
+            delay = delay.checked_mul(2).unwrap_or(delay);
+            attempt += 1;
         }
-        Err(UdtError::SockFail("invalid address".to_string()))
     }
+    /// Listens on the first of the resolved addresses that binds. Fails with
+    /// the error of the last attempt.
     pub fn listen<A: ToSocketAddrs>(self, addrs: A, backlog: i32) -> Result<UdtListener> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
         if let Ok(addrs) = addrs.to_socket_addrs() {
             for addr in addrs {
-                let socket = match addr {
-                    SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
-                    SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
-                };
-                self.config_socket(&socket)?;
-                let socket = socket.bind(addr)?;
-                socket.listen(backlog)?;
-                return Ok(UdtListener { socket });
+                match self.listen_addr(addr, backlog) {
+                    Ok(socket) => {
+                        return Ok(UdtListener {
+                            socket,
+                            shared: SharedSocket::new(socket),
+                        })
+                    }
+                    Err(e) => last_err = e,
+                }
             }
         }
-        Err(UdtError::SockFail("invalid address".to_string()))
+        Err(last_err)
+    }
+    /// Like `listen`, but also returns the address the listener is bound to,
+    /// which is useful when binding to port 0.
+    pub fn listen_with_addr<A: ToSocketAddrs>(
+        self,
+        addrs: A,
+        backlog: i32,
+    ) -> Result<(UdtListener, SocketAddr)> {
+        let listener = self.listen(addrs, backlog)?;
+        let addr = listener.local_addr()?;
+        Ok((listener, addr))
+    }
+    /// Binds to `local` in rendezvous mode and connects to `remote`. Both peers
+    /// must call this with each other's address; the call blocks until the
+    /// connection is set up or times out.
+    pub fn rendezvous<A: ToSocketAddrs, B: ToSocketAddrs>(
+        self,
+        local: A,
+        remote: B,
+    ) -> Result<UdtStream> {
+        self.set_rendezvous(true).bind(local)?.connect(remote)
     }
 }
 
 impl UdtBuilder {
+    /// Maximum packet size in bytes, including the IP, UDP and UDT headers, so
+    /// it should match the path MTU. Values below `MIN_MSS` make socket
+    /// creation fail with `InvParam`.
+    ///
+    /// UDT does not detect a path MTU smaller than this; oversized data packets
+    /// are dropped on the way. The connection is set up fine, since handshakes
+    /// are small, but then stalls and fails with `ConnLost`. If that happens
+    /// across a tunnel or VPN but not on the LAN, lower the MSS.
     pub fn set_mss(mut self, val: i32) -> Self {
         self.opt_vec.push(UdtSockOpt::Mss(val));
         self
     }
+    /// Maximum flow window in packets, which caps the receive buffer.
     pub fn set_fc(mut self, val: i32) -> Self {
         self.opt_vec.push(UdtSockOpt::Fc(val));
         self
@@ -204,6 +1194,13 @@ impl UdtBuilder {
         self.opt_vec.push(UdtSockOpt::Linger(val));
         self
     }
+    /// Sets linger on close: `None` closes without lingering, `Some` waits up
+    /// to that long for unsent data, rounded up to whole seconds.
+    pub fn set_linger_opt(mut self, linger: Option<time::Duration>) -> Self {
+        self.opt_vec
+            .push(UdtSockOpt::Linger(socket::linger_secs(linger)));
+        self
+    }
     pub fn set_rendezvous(mut self, val: bool) -> Self {
         self.opt_vec.push(UdtSockOpt::Rendezvous(val));
         self
@@ -216,6 +1213,20 @@ impl UdtBuilder {
         self.opt_vec.push(UdtSockOpt::RcvTimeo(val));
         self
     }
+    /// Sets both the send and receive timeouts, in whole milliseconds.
+    pub fn set_timeout(mut self, timeout: time::Duration) -> Self {
+        let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+        self.opt_vec.push(UdtSockOpt::SndTimeo(millis));
+        self.opt_vec.push(UdtSockOpt::RcvTimeo(millis));
+        self
+    }
+    /// Lets sockets in this process share a UDP port, on by default. Sockets
+    /// bound to the same address then share one UDP socket, but only one of
+    /// them may listen; `listen` on another fails with `DupListen`.
+    ///
+    /// This is not `SO_REUSEPORT`: UDT does not set any reuse option on the UDP
+    /// socket, so another process cannot bind the port at all. Have one process
+    /// listen and hand connections to workers instead.
     pub fn set_reuse_addr(mut self, val: bool) -> Self {
         self.opt_vec.push(UdtSockOpt::ReuseAddr(val));
         self
@@ -224,8 +1235,60 @@ impl UdtBuilder {
         self.opt_vec.push(UdtSockOpt::MaxBW(val));
         self
     }
-    fn config_socket(self, socket: &UdtSocket) -> Result<()> {
-        for opt in self.opt_vec {
+    /// The options configured so far, in the order they were set. They are
+    /// applied in a fixed order that UDT needs instead, see `UdtSockOpt`.
+    pub fn options(&self) -> &[UdtSockOpt] {
+        &self.opt_vec
+    }
+    fn connect_any<A: ToSocketAddrs>(&self, remote: &A) -> Result<UdtStream> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
+        if let Ok(addrs) = remote.to_socket_addrs() {
+            for addr in addrs {
+                match self.connect_addr(addr) {
+                    Ok(socket) => return Ok(UdtStream::new(socket)),
+                    Err(e) => last_err = e,
+                }
+            }
+        }
+        Err(last_err)
+    }
+    // Creates a socket of the same family as `addr` and binds it, closing the
+    // socket again if any step fails.
+    fn bind_addr(&self, addr: SocketAddr) -> Result<UdtSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
+            SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
+        };
+        close_on_err(
+            socket,
+            self.config_socket(&socket).and_then(|()| socket.bind(addr)),
+        )
+    }
+    // Like `bind_addr`, then starts listening.
+    fn listen_addr(&self, addr: SocketAddr, backlog: i32) -> Result<UdtSocket> {
+        let socket = self.bind_addr(addr)?;
+        close_on_err(socket, socket.listen(backlog))?;
+        Ok(socket)
+    }
+    // Creates a socket of the same family as `addr` and connects it, closing the
+    // socket again if any step fails.
+    fn connect_addr(&self, addr: SocketAddr) -> Result<UdtSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
+            SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
+        };
+        close_on_err(
+            socket,
+            self.config_socket(&socket)
+                .and_then(|()| socket.connect(addr)),
+        )?;
+        Ok(socket)
+    }
+    fn config_socket(&self, socket: &UdtSocket) -> Result<()> {
+        let mut opt_vec = self.opt_vec.clone();
+        // Stable, so a later setting of the same option still wins.
+        opt_vec.sort_by_key(UdtSockOpt::apply_rank);
+        for opt in opt_vec {
             match opt {
                 UdtSockOpt::Mss(val) => socket.set_mss(val)?,
                 UdtSockOpt::SndSyn(val) => socket.set_sndsyn(val)?,
@@ -247,37 +1310,163 @@ impl UdtBuilder {
     }
 }
 
+#[derive(Debug)]
 pub struct UdtAsyncStream {
     socket: UdtSocket,
+    // Cached so the peer stays known after the connection breaks.
+    peer_addr: Option<SocketAddr>,
+    // Set by `poll_close`, so Drop does not close the socket again.
+    closed: bool,
 }
 
 impl UdtAsyncStream {
+    fn new(socket: UdtSocket, peer_addr: Option<SocketAddr>) -> Self {
+        UdtAsyncStream {
+            socket,
+            peer_addr,
+            closed: false,
+        }
+    }
+    /// Turns the stream back into a blocking `UdtStream` on the same connection.
+    pub fn into_blocking(self) -> Result<UdtStream> {
+        self.socket.set_sndsyn(true)?;
+        self.socket.set_rcvsyn(true)?;
+        reactor::deregister(&self.socket);
+        // Skip our Drop, which would close the socket.
+        let this = mem::ManuallyDrop::new(self);
+        let mut stream = UdtStream::new(this.socket);
+        stream.peer_addr = this.peer_addr.or(stream.peer_addr);
+        Ok(stream)
+    }
+    /// Turns a blocking `UdtStream` into an async one on the same connection,
+    /// for use with any executor, e.g. async-std or tokio. Data queued by
+    /// `write_buffered` is sent first.
+    pub fn from_blocking(mut stream: UdtStream) -> Result<Self> {
+        stream.flush_queue()?;
+        stream.socket.set_sndsyn(false)?;
+        stream.socket.set_rcvsyn(false)?;
+        let peer_addr = stream.peer_addr.or_else(|| stream.socket.peer_addr().ok());
+        stream.write_queue = Vec::new();
+        // Skip its Drop, which would close the socket.
+        let stream = mem::ManuallyDrop::new(stream);
+        Ok(UdtAsyncStream::new(stream.socket, peer_addr))
+    }
+    /// The underlying UDT socket, e.g. to register it with a `UdtSelector`.
+    ///
+    /// The stream still owns the socket, so it must not be closed through the
+    /// returned handle.
+    pub fn as_udt_socket(&self) -> UdtSocket {
+        self.socket
+    }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
     }
+    /// The peer's address, as recorded when the connection was set up, so it
+    /// is still available once the connection has broken.
     pub fn peer_addr(&self) -> Result<SocketAddr> {
-        self.socket.peer_addr()
+        match self.peer_addr {
+            Some(addr) => Ok(addr),
+            None => self.socket.peer_addr(),
+        }
+    }
+    /// Reads into `buf` like `AsyncReadExt::read`, but resolves to `Ok(None)`
+    /// if nothing arrived within `timeout`. Only this read is bounded; the
+    /// socket's receive timeout is left alone.
+    pub fn read_timeout<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+        timeout: time::Duration,
+    ) -> ReadTimeout<'a> {
+        ReadTimeout {
+            stream: self,
+            buf,
+            deadline: time::Instant::now() + timeout,
+        }
+    }
+    /// Resolves once the send buffer has room for more data, without writing
+    /// anything, so backpressure can be checked ahead of a write.
+    pub fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.socket.snd_buf_room()? > 0 {
+            return Poll::Ready(Ok(()));
+        }
+        reactor::wake_when_ready(
+            &self.socket,
+            &udt_sys::EPOLLOpt::UDT_EPOLL_OUT,
+            cx.waker().clone(),
+        )?;
+        Poll::Pending
     }
 }
 
-impl AsyncRead for UdtAsyncStream {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
+//Socket option getters
+impl UdtAsyncStream {
+    /// Maximum packet size. After connect this is the value negotiated with
+    /// the peer, the smaller of the two sides' settings.
+    pub fn get_mss(&self) -> Result<i32> {
+        self.socket.get_mss()
+    }
+    /// Maximum number of packets in flight (`UDT_FC`).
+    pub fn get_flight_flag_size(&self) -> Result<i32> {
+        self.socket.get_fc()
+    }
+    /// All options as UDT currently reports them.
+    pub fn effective_config(&self) -> Result<EffectiveConfig> {
+        EffectiveConfig::read(&self.socket)
+    }
+    pub fn get_fc(&self) -> Result<i32> {
+        self.socket.get_fc()
+    }
+    pub fn get_snd_buf(&self) -> Result<i32> {
+        self.socket.get_sndbuf()
+    }
+    pub fn get_rcv_buf(&self) -> Result<i32> {
+        self.socket.get_rcvbuf()
+    }
+    pub fn get_udp_snd_buf(&self) -> Result<i32> {
+        self.socket.get_udp_sndbuf()
+    }
+    pub fn get_udp_rcv_buf(&self) -> Result<i32> {
+        self.socket.get_udp_rcvbuf()
+    }
+    pub fn get_linger(&self) -> Result<i32> {
+        self.socket.get_linger()
+    }
+    pub fn is_rendezvous(&self) -> Result<bool> {
+        self.socket.get_rendezvous()
+    }
+    pub fn get_snd_timeout(&self) -> Result<i32> {
+        self.socket.get_sndtimeo()
+    }
+    pub fn get_rcv_timeout(&self) -> Result<i32> {
+        self.socket.get_rcvtimeo()
+    }
+    pub fn get_reuse_addr(&self) -> Result<bool> {
+        self.socket.get_reuseaddr()
+    }
+    pub fn get_max_bw(&self) -> Result<i64> {
+        self.socket.get_maxbw()
+    }
+    pub fn get_state(&self) -> UdtStatus {
+        self.socket.get_state()
+    }
+}
+
+/// Reads fail with `ConnLost` once the connection is gone, as for `UdtStream`'s `Read`.
+impl AsyncRead for UdtAsyncStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
     ) -> Poll<std::result::Result<usize, io::Error>> {
         match self.socket.recv(buf) {
             Ok(s) => Poll::Ready(Ok(s)),
             Err(e) => match e {
                 UdtError::AsyncRcv(_) => {
-                    let waker = cx.waker().clone();
-                    let mut epoll = Epoll::new()?;
-                    epoll.add(&self.socket, &udt_sys::EPOLLOpt::UDT_EPOLL_IN)?;
-                    thread::spawn(move || {
-                        if let Ok(_) = epoll.wait(-1) {
-                            waker.wake();
-                        }
-                    });
+                    reactor::wake_when_ready(
+                        &self.socket,
+                        &udt_sys::EPOLLOpt::UDT_EPOLL_IN,
+                        cx.waker().clone(),
+                    )?;
                     Poll::Pending
                 }
                 e => Poll::Ready(Err(e.into())),
@@ -292,27 +1481,22 @@ impl AsyncWrite for UdtAsyncStream {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::result::Result<usize, io::Error>> {
+        // Ok(0) means the stream can no longer accept data, so only report it for an empty buf.
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
         match self.socket.send(buf) {
             Ok(s) => Poll::Ready(Ok(s)),
             Err(e) => match e {
-                UdtError::AsyncSnd(_) => match self.socket.get_snddata() {
-                    Ok(bytes) => {
-                        if bytes == 0 {
-                            Poll::Ready(Ok(0))
-                        } else {
-                            let waker = cx.waker().clone();
-                            let mut epoll = Epoll::new()?;
-                            epoll.add(&self.socket, &udt_sys::EPOLLOpt::UDT_EPOLL_OUT)?;
-                            thread::spawn(move || {
-                                if let Ok(_) = epoll.wait(-1) {
-                                    waker.wake();
-                                }
-                            });
-                            Poll::Pending
-                        }
-                    }
-                    Err(e) => Poll::Ready(Err(e.into())),
-                },
+                UdtError::AsyncSnd(_) => {
+                    // The send buffer is full, wait until it has room again.
+                    reactor::wake_when_ready(
+                        &self.socket,
+                        &udt_sys::EPOLLOpt::UDT_EPOLL_OUT,
+                        cx.waker().clone(),
+                    )?;
+                    Poll::Pending
+                }
                 e => Poll::Ready(Err(e.into())),
             },
         }
@@ -326,14 +1510,11 @@ impl AsyncWrite for UdtAsyncStream {
                 if bytes == 0 {
                     Poll::Ready(Ok(()))
                 } else {
-                    let waker = cx.waker().clone();
-                    let mut epoll = Epoll::new()?;
-                    epoll.add(&self.socket, &udt_sys::EPOLLOpt::UDT_EPOLL_OUT)?;
-                    thread::spawn(move || {
-                        if let Ok(_) = epoll.wait(-1) {
-                            waker.wake();
-                        }
-                    });
+                    reactor::wake_when_ready(
+                        &self.socket,
+                        &udt_sys::EPOLLOpt::UDT_EPOLL_OUT,
+                        cx.waker().clone(),
+                    )?;
                     Poll::Pending
                 }
             }
@@ -341,25 +1522,27 @@ impl AsyncWrite for UdtAsyncStream {
         }
     }
     fn poll_close(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<std::result::Result<(), io::Error>> {
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
         match self.socket.get_snddata() {
             Ok(bytes) => {
                 if bytes == 0 {
+                    reactor::deregister(&self.socket);
+                    self.closed = true;
                     Poll::Ready(match self.socket.close() {
                         Ok(()) => Ok(()),
                         Err(e) => Err(e.into()),
                     })
                 } else {
-                    let waker = cx.waker().clone();
-                    let mut epoll = Epoll::new()?;
-                    epoll.add(&self.socket, &udt_sys::EPOLLOpt::UDT_EPOLL_OUT)?;
-                    thread::spawn(move || {
-                        if let Ok(_) = epoll.wait(-1) {
-                            waker.wake();
-                        }
-                    });
+                    reactor::wake_when_ready(
+                        &self.socket,
+                        &udt_sys::EPOLLOpt::UDT_EPOLL_OUT,
+                        cx.waker().clone(),
+                    )?;
                     Poll::Pending
                 }
             }
@@ -370,7 +1553,15 @@ impl AsyncWrite for UdtAsyncStream {
 
 impl Drop for UdtAsyncStream {
     fn drop(&mut self) {
-        if let Err(_) = self.socket.close() {}
+        if self.closed {
+            return;
+        }
+        reactor::deregister(&self.socket);
+        // With non-blocking sends UDT lingers in the background instead of in
+        // close, so dropping never stalls the executor, whatever the linger
+        // setting or any blocking mode set through `as_udt_socket`.
+        let _ = self.socket.set_sndsyn(false);
+        close_on_drop(self.socket);
     }
 }
 
@@ -382,28 +1573,47 @@ impl UdtAsyncListener {
     pub fn accept(&self) -> AcceptFuture {
         AcceptFuture {
             socket: self.socket,
+            waker: None,
         }
     }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
     }
+    pub fn close(self) -> Result<()> {
+        // Skip our Drop, which would close the socket again.
+        mem::ManuallyDrop::new(self).socket.close()
+    }
 }
 
 impl Drop for UdtAsyncListener {
     fn drop(&mut self) {
-        if let Err(_) = self.socket.close() {}
+        close_on_drop(self.socket);
     }
 }
 
+/// Accepts one connection. Dropping it before it resolves, e.g. when it loses
+/// a `select!`, withdraws its registration with the reactor. Other accepts
+/// pending on the same listener stay registered.
 pub struct AcceptFuture {
     socket: UdtSocket,
+    // The waker last registered with the reactor, withdrawn on drop.
+    waker: Option<Waker>,
+}
+
+impl AcceptFuture {
+    fn withdraw(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            reactor::withdraw(&self.socket, &waker);
+        }
+    }
 }
 
 impl Future for AcceptFuture {
     type Output = Result<(UdtAsyncStream, SocketAddr)>;
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.socket.accept() {
             Ok((socket, addr)) => {
+                self.withdraw();
                 let r_b = socket.set_rcvsyn(false);
                 let s_b = socket.set_sndsyn(false);
                 if r_b.is_err() {
@@ -411,46 +1621,91 @@ impl Future for AcceptFuture {
                 } else if s_b.is_err() {
                     Poll::Ready(Err(s_b.expect_err("unreachable")))
                 } else {
-                    Poll::Ready(Ok((UdtAsyncStream { socket }, addr)))
+                    Poll::Ready(Ok((UdtAsyncStream::new(socket, Some(addr)), addr)))
                 }
             }
             Err(e) => match e {
                 UdtError::AsyncRcv(_) => {
-                    let waker = cx.waker().clone();
-                    let mut epoll = Epoll::new()?;
-                    epoll.add(&self.socket, &udt_sys::EPOLLOpt::UDT_EPOLL_IN)?;
-                    thread::spawn(move || {
-                        if let Ok(_) = epoll.wait(-1) {
-                            waker.wake();
-                        }
-                    });
+                    // Moved to another task since the last poll.
+                    if !matches!(&self.waker, Some(waker) if waker.will_wake(cx.waker())) {
+                        self.withdraw();
+                    }
+                    reactor::wake_when_ready(
+                        &self.socket,
+                        &udt_sys::EPOLLOpt::UDT_EPOLL_IN,
+                        cx.waker().clone(),
+                    )?;
+                    self.waker = Some(cx.waker().clone());
                     Poll::Pending
                 }
-                e => Poll::Ready(Err(e)),
+                e => {
+                    self.withdraw();
+                    Poll::Ready(Err(e))
+                }
             },
         }
     }
 }
 
+impl Drop for AcceptFuture {
+    fn drop(&mut self) {
+        self.withdraw();
+    }
+}
+
+/// Connects a socket. Dropping it before it resolves closes the socket.
 pub struct ConnectFuture {
     socket: UdtSocket,
+    deadline: Option<time::Instant>,
+    // Set once the socket was handed to a stream or closed, so Drop leaves it.
+    done: bool,
+}
+
+impl ConnectFuture {
+    fn new(socket: UdtSocket, deadline: Option<time::Instant>) -> Self {
+        ConnectFuture {
+            socket,
+            deadline,
+            done: false,
+        }
+    }
+    fn close(&mut self) {
+        self.done = true;
+        reactor::deregister(&self.socket);
+        close_on_drop(self.socket);
+    }
 }
 
 impl Future for ConnectFuture {
     type Output = Result<UdtAsyncStream>;
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.socket.get_state() {
             UdtStatus::Connecting => {
-                let waker = cx.waker().clone();
-                thread::spawn(move || {
-                    thread::sleep(time::Duration::from_millis(500));
-                    waker.wake();
-                });
+                if let Some(deadline) = self.deadline {
+                    if time::Instant::now() >= deadline {
+                        self.close();
+                        return Poll::Ready(Err(UdtError::Timeout(
+                            "connection timed out".to_string(),
+                        )));
+                    }
+                    timer::wake_at(deadline, cx.waker().clone());
+                }
+                // UDT signals a finished connect as writable and a failed one as an error.
+                reactor::wake_when_ready(
+                    &self.socket,
+                    &udt_sys::EPOLLOpt::UDT_EPOLL_OUT,
+                    cx.waker().clone(),
+                )?;
                 Poll::Pending
             }
-            UdtStatus::Connected => Poll::Ready(Ok(UdtAsyncStream {
-                socket: self.socket,
-            })),
+            UdtStatus::Connected => {
+                self.done = true;
+                reactor::deregister(&self.socket);
+                Poll::Ready(Ok(UdtAsyncStream::new(
+                    self.socket,
+                    self.socket.peer_addr().ok(),
+                )))
+            }
             UdtStatus::Broken => {
                 Poll::Ready(Err(UdtError::ConnLost("connection broken".to_string())))
             }
@@ -474,16 +1729,67 @@ impl Future for ConnectFuture {
     }
 }
 
+impl Drop for ConnectFuture {
+    fn drop(&mut self) {
+        if !self.done {
+            self.close();
+        }
+    }
+}
+
+/// A read bounded by a deadline, from `UdtAsyncStream::read_timeout`.
+pub struct ReadTimeout<'a> {
+    stream: &'a mut UdtAsyncStream,
+    buf: &'a mut [u8],
+    deadline: time::Instant,
+}
+
+impl Future for ReadTimeout<'_> {
+    type Output = Result<Option<usize>>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        match Pin::new(&mut *this.stream).poll_read(cx, this.buf) {
+            Poll::Ready(Ok(size)) => return Poll::Ready(Ok(Some(size))),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+            Poll::Pending => {}
+        }
+        if time::Instant::now() >= this.deadline {
+            return Poll::Ready(Ok(None));
+        }
+        // The shared timer wakes us at the deadline unless data comes first.
+        timer::wake_at(this.deadline, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// A socket bound to a local address but not yet connected, from
+/// `UdtAsyncBuilder::bind`.
 pub struct UdtBoundAsyncSocket {
     socket: UdtSocket,
 }
 
 impl UdtBoundAsyncSocket {
     pub fn connect<A: ToSocketAddrs>(self, remote: A) -> Result<ConnectFuture> {
-        self.socket.connect(remote)?;
-        Ok(ConnectFuture {
-            socket: self.socket,
-        })
+        self.start_connect(remote, None)
+    }
+    /// Like `connect`, but the returned future fails with `Timeout` and closes
+    /// the socket if the connection is not set up within `timeout`.
+    pub fn connect_timeout<A: ToSocketAddrs>(
+        self,
+        remote: A,
+        timeout: time::Duration,
+    ) -> Result<ConnectFuture> {
+        self.start_connect(remote, Some(time::Instant::now() + timeout))
+    }
+    fn start_connect<A: ToSocketAddrs>(
+        self,
+        remote: A,
+        deadline: Option<time::Instant>,
+    ) -> Result<ConnectFuture> {
+        // From here on the future owns the socket and closes it if need be.
+        let future = ConnectFuture::new(self.socket, deadline);
+        future.socket.connect(remote)?;
+        Ok(future)
     }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.socket.local_addr()
@@ -495,56 +1801,102 @@ pub struct UdtAsyncBuilder {
 }
 
 impl UdtAsyncBuilder {
+    /// Binds to the first of the resolved addresses that binds. Fails with the
+    /// error of the last attempt.
     pub fn bind<A: ToSocketAddrs>(self, local: A) -> Result<UdtBoundAsyncSocket> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
         if let Ok(addrs) = local.to_socket_addrs() {
             for addr in addrs {
-                let socket = match addr {
-                    SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
-                    SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
-                };
-                self.config_socket(&socket)?;
-                let socket = socket.bind(addr)?;
-                return Ok(UdtBoundAsyncSocket { socket });
+                match self.bind_addr(addr) {
+                    Ok(socket) => return Ok(UdtBoundAsyncSocket { socket }),
+                    Err(e) => last_err = e,
+                }
             }
         }
-        Err(UdtError::SockFail("invalid address".to_string()))
+        Err(last_err)
     }
+    /// Connects to the first of the resolved addresses that accepts, creating a
+    /// socket of the matching family for each attempt. Fails with the error of
+    /// the last attempt.
+    ///
+    /// Names are resolved on the calling thread, which blocks the executor on
+    /// a slow DNS lookup. Pass a `SocketAddr`, or use `connect_lookup`.
     pub fn connect<A: ToSocketAddrs>(self, remote: A) -> Result<ConnectFuture> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
         if let Ok(addrs) = remote.to_socket_addrs() {
             for addr in addrs {
-                let socket = match addr {
-                    SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
-                    SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
-                };
-                self.config_socket(&socket)?;
-                socket.connect(remote)?;
-                return Ok(ConnectFuture { socket });
+                match self.connect_addr(addr) {
+                    Ok(socket) => return Ok(ConnectFuture::new(socket, None)),
+                    Err(e) => last_err = e,
+                }
             }
         }
-        Err(UdtError::SockFail("invalid address".to_string()))
+        Err(last_err)
+    }
+    /// Like `connect`, but resolves `remote` on a separate thread so a slow
+    /// DNS lookup does not stall other tasks.
+    pub async fn connect_lookup<A: ToSocketAddrs + Send + 'static>(
+        self,
+        remote: A,
+    ) -> Result<UdtAsyncStream> {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        thread::spawn(move || {
+            let addrs = remote
+                .to_socket_addrs()
+                .map(|addrs| addrs.collect::<Vec<SocketAddr>>());
+            let _ = sender.send(addrs);
+        });
+        let addrs = match receiver.await {
+            Ok(Ok(addrs)) => addrs,
+            _ => return Err(UdtError::SockFail("invalid address".to_string())),
+        };
+        self.connect(&addrs[..])?.await
     }
+    /// Listens on the first of the resolved addresses that binds. Fails with
+    /// the error of the last attempt.
     pub fn listen<A: ToSocketAddrs>(self, addrs: A, backlog: i32) -> Result<UdtAsyncListener> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
         if let Ok(addrs) = addrs.to_socket_addrs() {
             for addr in addrs {
-                let socket = match addr {
-                    SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
-                    SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
-                };
-                self.config_socket(&socket)?;
-                let socket = socket.bind(addr)?;
-                socket.listen(backlog)?; // Still synchronous
-                return Ok(UdtAsyncListener { socket });
+                // listen itself is still synchronous
+                match self.listen_addr(addr, backlog) {
+                    Ok(socket) => return Ok(UdtAsyncListener { socket }),
+                    Err(e) => last_err = e,
+                }
             }
         }
-        Err(UdtError::SockFail("invalid address".to_string()))
+        Err(last_err)
+    }
+    /// Binds to `local` in rendezvous mode and starts connecting to `remote`.
+    /// Both peers must call this with each other's address, then await the
+    /// returned future.
+    pub fn rendezvous<A: ToSocketAddrs, B: ToSocketAddrs>(
+        self,
+        local: A,
+        remote: B,
+    ) -> Result<ConnectFuture> {
+        self.set_rendezvous(true).bind(local)?.connect(remote)
+    }
+    /// Like `listen`, but also returns the address the listener is bound to,
+    /// which is useful when binding to port 0.
+    pub fn listen_with_addr<A: ToSocketAddrs>(
+        self,
+        addrs: A,
+        backlog: i32,
+    ) -> Result<(UdtAsyncListener, SocketAddr)> {
+        let listener = self.listen(addrs, backlog)?;
+        let addr = listener.local_addr()?;
+        Ok((listener, addr))
     }
 }
 
 impl UdtAsyncBuilder {
+    /// Maximum packet size in bytes, see `UdtBuilder::set_mss`.
     pub fn set_mss(mut self, val: i32) -> Self {
         self.opt_vec.push(UdtSockOpt::Mss(val));
         self
     }
+    /// Maximum flow window in packets, which caps the receive buffer.
     pub fn set_fc(mut self, val: i32) -> Self {
         self.opt_vec.push(UdtSockOpt::Fc(val));
         self
@@ -569,6 +1921,13 @@ impl UdtAsyncBuilder {
         self.opt_vec.push(UdtSockOpt::Linger(val));
         self
     }
+    /// Sets linger on close: `None` closes without lingering, `Some` waits up
+    /// to that long for unsent data, rounded up to whole seconds.
+    pub fn set_linger_opt(mut self, linger: Option<time::Duration>) -> Self {
+        self.opt_vec
+            .push(UdtSockOpt::Linger(socket::linger_secs(linger)));
+        self
+    }
     pub fn set_rendezvous(mut self, val: bool) -> Self {
         self.opt_vec.push(UdtSockOpt::Rendezvous(val));
         self
@@ -581,6 +1940,14 @@ impl UdtAsyncBuilder {
         self.opt_vec.push(UdtSockOpt::RcvTimeo(val));
         self
     }
+    /// Sets both the send and receive timeouts, in whole milliseconds.
+    pub fn set_timeout(mut self, timeout: time::Duration) -> Self {
+        let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+        self.opt_vec.push(UdtSockOpt::SndTimeo(millis));
+        self.opt_vec.push(UdtSockOpt::RcvTimeo(millis));
+        self
+    }
+    /// Lets sockets in this process share a UDP port, see `UdtBuilder::set_reuse_addr`.
     pub fn set_reuse_addr(mut self, val: bool) -> Self {
         self.opt_vec.push(UdtSockOpt::ReuseAddr(val));
         self
@@ -589,8 +1956,48 @@ impl UdtAsyncBuilder {
         self.opt_vec.push(UdtSockOpt::MaxBW(val));
         self
     }
-    fn config_socket(self, socket: &UdtSocket) -> Result<()> {
-        for opt in self.opt_vec {
+    /// The options configured so far, in the order they were set. They are
+    /// applied in a fixed order that UDT needs instead, see `UdtSockOpt`.
+    pub fn options(&self) -> &[UdtSockOpt] {
+        &self.opt_vec
+    }
+    // Creates a socket of the same family as `addr` and binds it, closing the
+    // socket again if any step fails.
+    fn bind_addr(&self, addr: SocketAddr) -> Result<UdtSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
+            SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
+        };
+        close_on_err(
+            socket,
+            self.config_socket(&socket).and_then(|()| socket.bind(addr)),
+        )
+    }
+    // Like `bind_addr`, then starts listening.
+    fn listen_addr(&self, addr: SocketAddr, backlog: i32) -> Result<UdtSocket> {
+        let socket = self.bind_addr(addr)?;
+        close_on_err(socket, socket.listen(backlog))?;
+        Ok(socket)
+    }
+    // Creates a socket of the same family as `addr` and connects it, closing the
+    // socket again if any step fails.
+    fn connect_addr(&self, addr: SocketAddr) -> Result<UdtSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => UdtSocket::new_ipv4()?,
+            SocketAddr::V6(_) => UdtSocket::new_ipv6()?,
+        };
+        close_on_err(
+            socket,
+            self.config_socket(&socket)
+                .and_then(|()| socket.connect(addr)),
+        )?;
+        Ok(socket)
+    }
+    fn config_socket(&self, socket: &UdtSocket) -> Result<()> {
+        let mut opt_vec = self.opt_vec.clone();
+        // Stable, so a later setting of the same option still wins.
+        opt_vec.sort_by_key(UdtSockOpt::apply_rank);
+        for opt in opt_vec {
             match opt {
                 UdtSockOpt::Mss(val) => socket.set_mss(val)?,
                 UdtSockOpt::SndSyn(val) => socket.set_sndsyn(val)?,
@@ -612,8 +2019,17 @@ impl UdtAsyncBuilder {
     }
 }
 
-#[derive(Clone)]
-enum UdtSockOpt {
+/// A socket option recorded by a builder, applied when the socket is created.
+///
+/// Options are applied in the order they were set, except that `Mss` goes
+/// first, then `Fc`, then `SndBuf` and `RcvBuf`: UDT converts buffer sizes to
+/// packets of the MSS and caps the receive buffer at the flow window as they
+/// are set, so the other order would silently size the buffers wrong.
+///
+/// There is no variant for `UDT_CC`, see `UdtSocket::set_opt_raw`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UdtSockOpt {
     Mss(i32),
     SndSyn(bool),
     RcvSyn(bool),
@@ -630,6 +2046,18 @@ enum UdtSockOpt {
     MaxBW(i64),
 }
 
+impl UdtSockOpt {
+    // Position in which `config_socket` applies the option, see above.
+    fn apply_rank(&self) -> u8 {
+        match self {
+            UdtSockOpt::Mss(_) => 0,
+            UdtSockOpt::Fc(_) => 1,
+            UdtSockOpt::SndBuf(_) | UdtSockOpt::RcvBuf(_) => 2,
+            _ => 3,
+        }
+    }
+}
+
 struct Epoll {
     id: i32,
     num_rd_sock: usize,
@@ -661,41 +2089,52 @@ impl Epoll {
         if result == unsafe { udt_sys::UDT_ERROR } {
             error::get_error(())
         } else {
-            if *event & udt_sys::EPOLLOpt::UDT_EPOLL_IN == udt_sys::EPOLLOpt::UDT_EPOLL_IN {
-                self.num_rd_sock += 1;
-            }
-
-            if *event & udt_sys::EPOLLOpt::UDT_EPOLL_OUT == udt_sys::EPOLLOpt::UDT_EPOLL_OUT {
-                self.num_wr_sock += 1;
-            }
+            let (rd, wr) = Self::slots(*event);
+            self.num_rd_sock += rd;
+            self.num_wr_sock += wr;
             Ok(())
         }
     }
-    #[allow(dead_code)]
-    fn remove(&mut self, socket: &UdtSocket) -> Result<()> {
-        let event = socket.get_event()?;
+    // `event` must be what the socket was added with, to keep the counts right.
+    fn remove(&mut self, socket: &UdtSocket, event: &udt_sys::EPOLLOpt) -> Result<()> {
+        let event = *event;
         let result = unsafe { udt_sys::udt_epoll_remove_usock(self.id, socket.id) };
         if result == unsafe { udt_sys::UDT_ERROR } {
             error::get_error(())
         } else {
-            if event & udt_sys::EPOLLOpt::UDT_EPOLL_IN == udt_sys::EPOLLOpt::UDT_EPOLL_IN {
-                self.num_rd_sock -= 1;
-            }
-
-            if event & udt_sys::EPOLLOpt::UDT_EPOLL_OUT == udt_sys::EPOLLOpt::UDT_EPOLL_OUT {
-                self.num_wr_sock -= 1;
-            }
+            let (rd, wr) = Self::slots(event);
+            self.num_rd_sock -= rd;
+            self.num_wr_sock -= wr;
             Ok(())
         }
     }
+    // Room a socket added with `event` needs in the read and write lists. UDT
+    // reports sockets with errors in both.
+    fn slots(event: udt_sys::EPOLLOpt) -> (usize, usize) {
+        let has = |flag: udt_sys::EPOLLOpt| event & flag == flag;
+        let err = has(udt_sys::EPOLLOpt::UDT_EPOLL_ERR);
+        (
+            (has(udt_sys::EPOLLOpt::UDT_EPOLL_IN) || err) as usize,
+            (has(udt_sys::EPOLLOpt::UDT_EPOLL_OUT) || err) as usize,
+        )
+    }
     fn wait(&self, timeout: i64) -> Result<(Vec<udt_sys::UDTSOCKET>, Vec<udt_sys::UDTSOCKET>)> {
-        let mut rd_array = vec![unsafe { udt_sys::UDT_INVALID_SOCK }; self.num_rd_sock];
+        Self::wait_on(self.id, self.num_rd_sock, self.num_wr_sock, timeout)
+    }
+    // Waits on the set `id` without borrowing it, so it can be added to meanwhile.
+    fn wait_on(
+        id: i32,
+        num_rd_sock: usize,
+        num_wr_sock: usize,
+        timeout: i64,
+    ) -> Result<(Vec<udt_sys::UDTSOCKET>, Vec<udt_sys::UDTSOCKET>)> {
+        let mut rd_array = vec![unsafe { udt_sys::UDT_INVALID_SOCK }; num_rd_sock];
         let mut rd_len = rd_array.len() as c_int;
-        let mut wr_array = vec![unsafe { udt_sys::UDT_INVALID_SOCK }; self.num_wr_sock];
+        let mut wr_array = vec![unsafe { udt_sys::UDT_INVALID_SOCK }; num_wr_sock];
         let mut wr_len = wr_array.len() as c_int;
         let result = unsafe {
             udt_sys::udt_epoll_wait2(
-                self.id,
+                id,
                 rd_array[..].as_mut_ptr() as *mut udt_sys::UDTSOCKET,
                 &mut rd_len as *mut i32,
                 wr_array[..].as_mut_ptr() as *mut udt_sys::UDTSOCKET,
@@ -738,16 +2177,18 @@ impl Drop for Epoll {
 mod tests {
     use crate as udt;
     use futures::{
-        executor::block_on,
+        executor::{block_on, ThreadPool},
         future,
         io::{AsyncReadExt, AsyncWriteExt},
     };
     use std::{
-        io::{Read, Write},
+        io::{self, Read, Write},
         net::SocketAddr,
         sync::mpsc,
         thread,
+        time::{Duration, Instant},
     };
+    use udt::error::UdtError;
 
     #[test]
     fn test_ipv6() {
@@ -878,6 +2319,36 @@ mod tests {
         udt::cleanup().expect("failed cleanup()");
     }
 
+    #[test]
+    fn test_async_connect_lookup() {
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let port = listen.local_addr().expect("fail local_addr()").port();
+        let listen_task = async move {
+            let (mut peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            peer.write_all(b"testing").await.expect("fail write()");
+            assert!(peer.close().await.is_ok());
+        };
+        let connect_task = async move {
+            let mut connect = udt::async_builder()
+                .connect_lookup(format!("127.0.0.1:{}", port))
+                .await
+                .expect("fail connect_lookup()");
+            let mut buf = [0; 7];
+            connect.read_exact(&mut buf).await.expect("fail read()");
+            assert_eq!(&buf, b"testing");
+            assert!(connect.close().await.is_ok());
+        };
+        block_on(future::join(listen_task, connect_task));
+        match block_on(udt::async_builder().connect_lookup("no such host")) {
+            Err(UdtError::SockFail(_)) => {}
+            _ => panic!("expected SockFail for an unresolvable address"),
+        }
+        udt::cleanup().expect("failed cleanup()");
+    }
+
     #[test]
     fn test_ipv4_rendezvous() {
         udt::startup().expect("failed startup");
@@ -914,4 +2385,3101 @@ mod tests {
         assert!(two.close().is_ok());
         udt::cleanup().expect("failed cleanup");
     }
+
+    #[test]
+    fn test_ipv4_rendezvous_async() {
+        udt::startup().expect("failed startup");
+        // Rendezvous peers need each other's address up front, so reserve two
+        // free ports and release them for UDT.
+        let free_addr = |ip: &str| {
+            std::net::UdpSocket::bind((ip, 0))
+                .and_then(|socket| socket.local_addr())
+                .expect("fail reserve port")
+        };
+        let addr_1 = free_addr("127.0.0.1");
+        let addr_2 = free_addr("127.0.0.2");
+        let one = udt::async_builder()
+            .set_reuse_addr(false)
+            .rendezvous(addr_1, addr_2)
+            .expect("fail rendezvous()");
+        let two = udt::async_builder()
+            .set_reuse_addr(false)
+            .rendezvous(addr_2, addr_1)
+            .expect("fail rendezvous()");
+        block_on(async {
+            let (one, two) = future::join(one, two).await;
+            let mut one = one.expect("fail connect()");
+            let mut two = two.expect("fail connect()");
+            one.write_all(b"testing").await.expect("fail write()");
+            let mut buf = [0; 7];
+            two.read_exact(&mut buf).await.expect("fail read()");
+            assert_eq!(&buf, b"testing");
+        });
+        udt::cleanup().expect("failed cleanup");
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        // Everything the peer receives until the stream ends or fails.
+        fn read_until_end(stream: &mut udt::UdtStream) -> usize {
+            let mut buf = vec![0; 64 * 1024];
+            let mut received = 0;
+            while let Ok(size) = stream.read(&mut buf) {
+                if size == 0 {
+                    break;
+                }
+                received += size;
+            }
+            received
+        }
+        udt::startup().expect("failed startup");
+        // A tiny receive buffer on the peer keeps most of the payload unacknowledged.
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .set_rcv_buf(1)
+            .listen("127.0.0.1:0", 2)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let payload = vec![0; 1_000_000];
+
+        // The default policy lingers, so a reading peer gets every byte.
+        let linger_listen = listen.try_clone().expect("fail try_clone()");
+        let reader = thread::spawn(move || {
+            let (mut peer, _) = linger_listen.accept().expect("fail accept()");
+            read_until_end(&mut peer)
+        });
+        let mut linger = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        assert_eq!(linger.drop_policy(), udt::DropPolicy::Linger);
+        linger.write_all(&payload).expect("fail write()");
+        drop(linger);
+        assert_eq!(reader.join().expect("reader panicked"), payload.len());
+
+        // Abort discards what the peer had not acknowledged when it was dropped.
+        let (tx, rx) = mpsc::channel::<()>();
+        let reader = thread::spawn(move || {
+            let (mut peer, _) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+            read_until_end(&mut peer)
+        });
+        let mut abort = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        abort.set_drop_policy(udt::DropPolicy::Abort);
+        abort.write_all(&payload).expect("fail write()");
+        drop(abort);
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(reader.join().expect("reader panicked") < payload.len());
+
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[cfg(feature = "debug-reactor")]
+    #[test]
+    fn test_reactor_debug() {
+        udt::startup().expect("failed startup");
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        udt::reactor::wake_when_ready(
+            &socket,
+            &udt_sys::EPOLLOpt::UDT_EPOLL_IN,
+            futures::task::noop_waker(),
+        )
+        .expect("fail wake_when_ready()");
+        let entries = udt::reactor_debug();
+        assert!(entries.contains(&udt::ReactorEntry {
+            socket: socket.id,
+            read: true,
+            write: false,
+        }));
+        udt::reactor::deregister(&socket);
+        assert!(udt::reactor_debug()
+            .iter()
+            .all(|entry| entry.socket != socket.id));
+        socket.close().expect("fail close()");
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[cfg(feature = "debug-reactor")]
+    #[test]
+    fn test_accept_future_drop() {
+        use std::{future::Future, pin::Pin, task::Context};
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let socket = listen.socket;
+        let mut accept = listen.accept();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut accept).poll(&mut cx).is_pending());
+        assert!(udt::reactor_debug()
+            .iter()
+            .any(|entry| entry.socket == socket.id));
+        drop(accept);
+        assert!(udt::reactor_debug()
+            .iter()
+            .all(|entry| entry.socket != socket.id));
+        assert!(listen.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[cfg(feature = "debug-reactor")]
+    #[test]
+    fn test_concurrent_accepts() {
+        use std::{
+            future::Future,
+            pin::Pin,
+            sync::{
+                atomic::{AtomicBool, Ordering},
+                Arc,
+            },
+            task::{Context, Poll, Wake, Waker},
+        };
+        struct Flag(AtomicBool);
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 2)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let socket = listen.socket;
+        let first_flag = Arc::new(Flag(AtomicBool::new(false)));
+        let second_flag = Arc::new(Flag(AtomicBool::new(false)));
+        let first_waker = Waker::from(first_flag.clone());
+        let second_waker = Waker::from(second_flag.clone());
+        let mut first = listen.accept();
+        let mut second = listen.accept();
+        assert!(Pin::new(&mut first)
+            .poll(&mut Context::from_waker(&first_waker))
+            .is_pending());
+        assert!(Pin::new(&mut second)
+            .poll(&mut Context::from_waker(&second_waker))
+            .is_pending());
+        // Dropping one accept leaves the other one waiting.
+        drop(first);
+        assert!(udt::reactor_debug()
+            .iter()
+            .any(|entry| entry.socket == socket.id && entry.read));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let started = Instant::now();
+        while !second_flag.0.load(Ordering::SeqCst) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!first_flag.0.load(Ordering::SeqCst));
+        match Pin::new(&mut second).poll(&mut Context::from_waker(&second_waker)) {
+            Poll::Ready(Ok((stream, _))) => drop(stream),
+            _ => panic!("accept did not complete after its wake-up"),
+        }
+        drop(second);
+        assert!(connect.close().is_ok());
+        assert!(listen.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_send_recv_bytes() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (peer, _peer_addr) = handle.join().expect("accept panicked");
+        let sent = connect
+            .as_udt_socket()
+            .send_bytes(&bytes::Bytes::from_static(b"testing"))
+            .expect("fail send_bytes()");
+        assert_eq!(sent, 7);
+        let mut buf = bytes::BytesMut::with_capacity(16);
+        let mut received = 0;
+        while received < 7 {
+            received += peer
+                .as_udt_socket()
+                .recv_bytes(&mut buf)
+                .expect("fail recv_bytes()");
+        }
+        assert_eq!(&buf[..], b"testing");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_error_display() {
+        let e = UdtError::ConnLost("connection has been broken".to_string());
+        assert_eq!(e.code(), 2001);
+        assert_eq!(e.message(), "connection has been broken");
+        assert_eq!(e.to_string(), "ConnLost (2001): connection has been broken");
+        let e = UdtError::PeerErr("bad marker".to_string());
+        assert_eq!(e.to_string(), "PeerErr (7000): bad marker");
+    }
+
+    #[test]
+    fn test_io_error_kinds() {
+        let kinds = [
+            (UdtError::Timeout(String::new()), io::ErrorKind::TimedOut),
+            (
+                UdtError::ConnLost(String::new()),
+                io::ErrorKind::ConnectionAborted,
+            ),
+            (UdtError::NoConn(String::new()), io::ErrorKind::NotConnected),
+            (
+                UdtError::NoServer(String::new()),
+                io::ErrorKind::ConnectionRefused,
+            ),
+            (
+                UdtError::ConnRej(String::new()),
+                io::ErrorKind::ConnectionRefused,
+            ),
+            (UdtError::AsyncRcv(String::new()), io::ErrorKind::WouldBlock),
+            (UdtError::AsyncSnd(String::new()), io::ErrorKind::WouldBlock),
+        ];
+        for (e, kind) in kinds.iter() {
+            assert_eq!(io::Error::from(e.clone()).kind(), *kind);
+        }
+
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (mut peer, _peer_addr) = handle.join().expect("accept panicked");
+        let mut buf = [0; 16];
+
+        peer.set_rcv_timeout(100).expect("fail set_rcv_timeout()");
+        let e = peer.read(&mut buf).expect_err("read should time out");
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        peer.set_nonblocking(true).expect("fail set_nonblocking()");
+        let e = peer.read(&mut buf).expect_err("read should not block");
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        peer.set_nonblocking(false).expect("fail set_nonblocking()");
+
+        // A peer close is reported like a broken link, not as end of stream.
+        assert!(connect.close().is_ok());
+        let e = loop {
+            match peer.read(&mut buf) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                result => break result.expect_err("read should fail after peer close"),
+            }
+        };
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_builder_set_timeout() {
+        let builder = udt::builder().set_timeout(Duration::from_secs(5));
+        assert_eq!(
+            builder.options(),
+            &[
+                udt::UdtSockOpt::SndTimeo(5000),
+                udt::UdtSockOpt::RcvTimeo(5000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_linger_opt() {
+        let builder = udt::builder()
+            .set_linger_opt(None)
+            .set_linger_opt(Some(Duration::from_millis(1500)));
+        assert_eq!(
+            builder.options(),
+            &[udt::UdtSockOpt::Linger(0), udt::UdtSockOpt::Linger(2)]
+        );
+        udt::startup().expect("failed startup");
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        socket
+            .set_linger_opt(Some(Duration::from_secs(3)))
+            .expect("fail set_linger_opt()");
+        assert_eq!(socket.get_linger().expect("fail get_linger()"), 3);
+        socket.set_linger_opt(None).expect("fail set_linger_opt()");
+        assert_eq!(socket.get_linger().expect("fail get_linger()"), 0);
+        socket.close().expect("fail close()");
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_builder_error_closes_socket() {
+        udt::startup().expect("failed startup");
+        assert!(matches!(
+            udt::builder().set_mss(10).listen("127.0.0.1:0", 1),
+            Err(UdtError::InvParam(_))
+        ));
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        let result: udt::Result<()> = udt::close_on_err(
+            socket,
+            Err(UdtError::InvParam("forced failure".to_string())),
+        );
+        assert!(result.is_err());
+        assert!(matches!(
+            socket.get_state(),
+            udt::UdtStatus::Closed | udt::UdtStatus::NonExist
+        ));
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_connect_retry() {
+        udt::startup().expect("failed startup");
+        let addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .and_then(|socket| socket.local_addr())
+            .expect("fail pick port");
+        // Start listening only after the first attempt has timed out.
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_secs(4));
+            let listen = udt::builder()
+                .set_reuse_addr(false)
+                .listen(addr, 1)
+                .expect("fail listen()");
+            let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+            peer
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect_retry(addr, 3, Duration::from_millis(200))
+            .expect("fail connect_retry()");
+        let peer = handle.join().expect("fail join()");
+        assert_eq!(connect.peer_addr().expect("fail peer_addr()"), addr);
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_builder_options() {
+        let builder = udt::builder()
+            .set_mss(1400)
+            .set_udp_snd_buf(2048000)
+            .set_rendezvous(true);
+        assert_eq!(
+            builder.options(),
+            &[
+                udt::UdtSockOpt::Mss(1400),
+                udt::UdtSockOpt::UdpSndBuf(2048000),
+                udt::UdtSockOpt::Rendezvous(true),
+            ]
+        );
+        let builder = udt::async_builder().set_max_bw(1_000_000);
+        assert_eq!(
+            builder.options(),
+            &[
+                udt::UdtSockOpt::RcvSyn(false),
+                udt::UdtSockOpt::SndSyn(false),
+                udt::UdtSockOpt::MaxBW(1_000_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try_recv() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (_peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        socket.set_reuseaddr(false).expect("fail set_reuseaddr()");
+        socket.connect(addr).expect("fail connect()");
+        let mut buf = [0; 7];
+        assert!(matches!(socket.try_recv(&mut buf), Ok(None)));
+        assert!(socket.get_rcvsyn().expect("fail get_rcvsyn()"));
+        tx.send(()).expect("fail send through mpsc channel");
+        socket.close().expect("fail close()");
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_cleanup_without_startup() {
+        let mut count = 0;
+        assert!(matches!(
+            udt::release_startup(&mut count),
+            Err(udt::UdtError::InvOp(_))
+        ));
+        assert_eq!(count, 0);
+        count = 1;
+        assert!(udt::release_startup(&mut count).is_ok());
+        assert!(udt::release_startup(&mut count).is_err());
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_try_send_all() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (_peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(64000)
+            .connect(addr)
+            .expect("fail connect()");
+        let buf = vec![0; 1_000_000];
+        assert!(!connect.try_send_all(&buf).expect("fail try_send_all()"));
+        assert_eq!(
+            connect
+                .as_udt_socket()
+                .get_snddata()
+                .expect("fail get_snddata()"),
+            0
+        );
+        assert!(connect
+            .try_send_all(b"testing")
+            .expect("fail try_send_all()"));
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_try_send_all_partly_filled() {
+        udt::startup().expect("failed startup");
+        // The smallest receive buffer UDT allows, 32 packets, so the peer stops
+        // acknowledging long before the sender's 128 packets are used up.
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .set_rcv_buf(1)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (_peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(128 * 1472)
+            .connect(addr)
+            .expect("fail connect()");
+        let payload = connect
+            .packet_payload_size()
+            .expect("fail packet_payload_size()");
+        let mss = connect.get_mss().expect("fail get_mss()");
+        let capacity = (connect.get_snd_buf().expect("fail get_snd_buf()") / (mss - 28)) as usize;
+        connect
+            .write_all(&vec![0; 64 * payload])
+            .expect("fail write()");
+        // Wait for the peer's buffer to fill, after which nothing more is acknowledged.
+        let socket = connect.as_udt_socket();
+        let mut queued = socket.get_snddata().expect("fail get_snddata()");
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let now = socket.get_snddata().expect("fail get_snddata()");
+            if now == queued {
+                break;
+            }
+            queued = now;
+        }
+        assert!(queued > 0 && (queued as usize) < capacity);
+        let room = (capacity - queued as usize) * payload;
+        assert!(connect.can_send(room).expect("fail can_send()"));
+        assert!(!connect.can_send(room + 1).expect("fail can_send()"));
+        assert!(!connect
+            .try_send_all(&vec![0; room + 1])
+            .expect("fail try_send_all()"));
+        assert_eq!(socket.get_snddata().expect("fail get_snddata()"), queued);
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(connect.abort().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_runtime_nested() {
+        let outer = udt::UdtRuntime::init().expect("fail UdtRuntime::init()");
+        {
+            let _inner = udt::UdtRuntime::init().expect("fail UdtRuntime::init()");
+        }
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        socket.close().expect("fail close()");
+        drop(outer);
+    }
+
+    #[test]
+    fn test_poll_readiness() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+            peer.write_all(b"testing").expect("fail write()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let socket = connect.as_udt_socket();
+        let interest = udt_sys::EPOLLOpt::UDT_EPOLL_IN;
+        let idle = socket
+            .poll_readiness(&interest)
+            .expect("fail poll_readiness()");
+        assert!(!idle.readable);
+        tx.send(()).expect("fail send through mpsc channel");
+        let start = Instant::now();
+        let mut ready = idle;
+        while !ready.readable && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+            ready = socket
+                .poll_readiness(&interest)
+                .expect("fail poll_readiness()");
+        }
+        assert!(ready.readable);
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_accept_into() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 2)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let pool = ThreadPool::builder()
+            .pool_size(2)
+            .create()
+            .expect("fail create ThreadPool");
+        let (tx, rx) = mpsc::channel::<SocketAddr>();
+        thread::spawn(move || {
+            for _ in 0..2 {
+                let tx = tx.clone();
+                listen
+                    .accept_into(&pool, move |mut stream, peer_addr| {
+                        let mut buf = [0; 7];
+                        stream.read_exact(&mut buf).expect("fail read()");
+                        assert_eq!(&buf, b"testing");
+                        assert!(stream.close().is_ok());
+                        tx.send(peer_addr).expect("fail send through mpsc channel");
+                    })
+                    .expect("fail accept_into()");
+            }
+        });
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            connect.write_all(b"testing").expect("fail write()");
+            clients.push(connect);
+        }
+        let mut handled = vec![
+            rx.recv().expect("fail recv through mpsc channel"),
+            rx.recv().expect("fail recv through mpsc channel"),
+        ];
+        let mut expected: Vec<SocketAddr> = clients
+            .iter()
+            .map(|client| client.local_addr().expect("fail local_addr()"))
+            .collect();
+        handled.sort();
+        expected.sort();
+        assert_eq!(handled, expected);
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_async_write_all_slow_reader() {
+        udt::startup().expect("failed startup");
+        let payload: Vec<u8> = (0..4_000_000).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(64000)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let reader = thread::spawn(move || {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            let mut received = vec![0; expected.len()];
+            for chunk in received.chunks_mut(65536) {
+                thread::sleep(Duration::from_millis(1));
+                connect.read_exact(chunk).expect("fail read()");
+            }
+            assert!(received == expected);
+        });
+        block_on(async {
+            let (mut peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            peer.write_all(&payload).await.expect("fail write()");
+            peer.flush().await.expect("fail flush()");
+        });
+        reader.join().expect("reader panicked");
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_skip_large() {
+        udt::startup().expect("failed startup");
+        udt::set_discard_buffer_size(1 << 20);
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            peer.write_all(&vec![0xff; 3_000_000])
+                .expect("fail write()");
+            peer.write_all(b"testing").expect("fail write()");
+            let mut buf = [0; 1];
+            peer.read_exact(&mut buf).expect("fail read()");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        assert_eq!(connect.skip(3_000_000).expect("fail skip()"), 3_000_000);
+        let mut buf = [0; 7];
+        connect.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(
+            std::str::from_utf8(&buf).expect("malformed message"),
+            "testing"
+        );
+        connect.write_all(b"x").expect("fail write()");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_bandwidth_smoothing() {
+        let mut average = f64::NAN;
+        for _ in 0..10 {
+            average = udt::ewma(average, 100.0, udt::BANDWIDTH_SMOOTHING);
+        }
+        assert_eq!(average, 100.0);
+        // A step up is followed gradually, never overshooting.
+        average = udt::ewma(average, 200.0, udt::BANDWIDTH_SMOOTHING);
+        assert_eq!(average, 112.5);
+        let mut previous = average;
+        for _ in 0..15 {
+            average = udt::ewma(average, 200.0, udt::BANDWIDTH_SMOOTHING);
+            assert!(average > previous && average < 200.0);
+            previous = average;
+        }
+        assert!((average - 200.0).abs() < 15.0);
+        // And a step down the same way.
+        average = udt::ewma(average, 0.0, udt::BANDWIDTH_SMOOTHING);
+        assert!(average > 150.0);
+    }
+
+    #[test]
+    fn test_loss_based_rate_control() {
+        let config = udt::RateControlConfig {
+            min_bw: 1_000,
+            max_bw: 100_000,
+            decrease: 0.5,
+            increase: 10_000,
+            interval: std::time::Duration::from_millis(10),
+        };
+        let mut controller = udt::rate_control::RateController::new(config, 5);
+        // No new loss keeps the limit at the top, where it starts.
+        assert_eq!(controller.update(5), 100_000);
+        // Each interval with loss cuts it, down to the floor.
+        assert_eq!(controller.update(8), 50_000);
+        assert_eq!(controller.update(9), 25_000);
+        let mut limit = 0;
+        for lost_total in 10..20 {
+            limit = controller.update(lost_total);
+        }
+        assert_eq!(limit, 1_000);
+        // Without loss it climbs back additively.
+        assert_eq!(controller.update(19), 11_000);
+        assert_eq!(controller.update(19), 21_000);
+
+        let bad = udt::RateControlConfig {
+            decrease: 1.5,
+            ..config
+        };
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_io_error_keeps_udt_error() {
+        let err = io::Error::from(UdtError::ConnLost("connection was broken".to_string()));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        let inner = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UdtError>())
+            .expect("io::Error lost the UdtError");
+        assert!(matches!(inner, UdtError::ConnLost(_)));
+        match UdtError::from(err) {
+            UdtError::ConnLost(msg) => assert_eq!(msg, "connection was broken"),
+            e => panic!("unexpected error {:?}", e),
+        }
+        // Errors that did not come from UDT keep their kind instead of turning
+        // into a UDT variant, and stay reachable through `source`.
+        let err = UdtError::from(io::Error::new(io::ErrorKind::TimedOut, "deadline"));
+        assert!(matches!(err, UdtError::Io(_)));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::TimedOut);
+        let err = UdtError::from(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"));
+        assert!(matches!(err, UdtError::Io(_)));
+        assert_eq!(err.code(), -1);
+        assert_eq!(err.to_string(), "Io: pipe closed");
+        let source = std::error::Error::source(&err).expect("Io error lost its source");
+        let source = source
+            .downcast_ref::<io::Error>()
+            .expect("source is not an io::Error");
+        assert_eq!(source.kind(), io::ErrorKind::BrokenPipe);
+        assert!(std::error::Error::source(&UdtError::ConnLost(String::new())).is_none());
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_peak_occupancy() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut buf = vec![0; 2_000_000];
+            peer.read_exact(&mut buf).expect("fail read()");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        connect
+            .write_all(&vec![0; 2_000_000])
+            .expect("fail write()");
+        let start = Instant::now();
+        while connect
+            .packets_buffered_to_send()
+            .expect("fail get_snddata()")
+            > 0
+            && start.elapsed() < Duration::from_secs(10)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let last = connect
+            .packets_buffered_to_send()
+            .expect("fail get_snddata()");
+        assert!(connect.peak_send_occupancy() > last);
+        connect.reset_peaks();
+        assert_eq!(connect.peak_send_occupancy(), 0);
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_buffer_occupancy_in_packets() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let payload = peer
+                .packet_payload_size()
+                .expect("fail packet_payload_size()");
+            peer.write_all(&vec![0; 10 * payload])
+                .expect("fail write()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let start = Instant::now();
+        while connect
+            .packets_available_to_read()
+            .expect("fail get_rcvdata()")
+            < 10
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            connect
+                .packets_available_to_read()
+                .expect("fail get_rcvdata()"),
+            10
+        );
+        assert_eq!(
+            connect
+                .packets_buffered_to_send()
+                .expect("fail get_snddata()"),
+            0
+        );
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_peek_ready() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        let peer_thread = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+            peer.write_all(b"testing").expect("fail write()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        assert!(!connect.peek_ready().expect("fail peek_ready()"));
+        tx.send(()).expect("fail send through mpsc channel");
+        let start = Instant::now();
+        while !connect.peek_ready().expect("fail peek_ready()")
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(connect.peek_ready().expect("fail peek_ready()"));
+        let mut buf = [0; 7];
+        connect.read_exact(&mut buf).expect("fail read()");
+        assert!(!connect.peek_ready().expect("fail peek_ready()"));
+        tx.send(()).expect("fail send through mpsc channel");
+        peer_thread.join().expect("peer thread panicked");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_recording_stream() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let handle = thread::spawn(move || {
+            let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut peer = udt::RecordingStream::new(peer);
+            let mut buf = vec![0; 3210];
+            peer.read_exact(&mut buf).expect("fail read()");
+            assert_eq!(peer.read_sizes().iter().sum::<usize>(), 3210);
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let mut connect = udt::RecordingStream::new(connect);
+        for size in &[10, 200, 3000] {
+            let written = connect.write(&vec![0; *size]).expect("fail write()");
+            assert_eq!(written, *size);
+        }
+        assert_eq!(connect.write_sizes(), vec![10, 200, 3000]);
+        assert!(connect.read_sizes().is_empty());
+        handle.join().expect("fail join()");
+        assert!(connect.into_inner().close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_listen_with_addr() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        assert_ne!(addr.port(), 0);
+        assert_eq!(listen.local_addr().expect("fail local_addr()"), addr);
+        let handle = thread::spawn(move || udt::builder().connect(addr).expect("fail connect()"));
+        let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+        let connect = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        assert!(listen.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_rendezvous_helper() {
+        udt::startup().expect("failed startup");
+        // Borrow free ports from the OS; the helper needs both addresses up front.
+        let free_addr = |ip: &str| {
+            std::net::UdpSocket::bind((ip, 0))
+                .and_then(|socket| socket.local_addr())
+                .expect("fail to reserve a port")
+        };
+        let one_addr = free_addr("127.0.0.1");
+        let two_addr = free_addr("127.0.0.2");
+        let handle = thread::spawn(move || {
+            let mut one = udt::builder()
+                .set_reuse_addr(false)
+                .rendezvous(one_addr, two_addr)
+                .expect("fail rendezvous()");
+            one.write_all(b"testing").expect("fail write()");
+            assert!(one.close().is_ok());
+        });
+        let mut two = udt::builder()
+            .set_reuse_addr(false)
+            .rendezvous(two_addr, one_addr)
+            .expect("fail rendezvous()");
+        assert_eq!(two.peer_addr().expect("fail peer_addr()"), one_addr);
+        let mut buf = [0; 7];
+        two.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(&buf, b"testing");
+        handle.join().expect("fail join()");
+        assert!(two.close().is_ok());
+        udt::cleanup().expect("failed cleanup");
+    }
+
+    #[test]
+    fn test_socket_family_debug() {
+        udt::startup().expect("failed startup");
+        let socket = udt::UdtSocket::new_ipv6().expect("fail new_ipv6()");
+        assert_eq!(socket.family(), udt::AddrFamily::Ipv6);
+        assert!(socket.local_addr().is_err());
+        assert!(format!("{:?}", socket).contains("ipv6"));
+        assert!(socket.close().is_ok());
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        assert!(format!("{:?}", socket).contains("ipv4"));
+        assert!(socket.close().is_ok());
+        udt::cleanup().expect("failed cleanup");
+    }
+
+    #[test]
+    fn test_async_wait_timeout() {
+        udt::startup().expect("failed startup");
+        // Readiness arrives long after the first wait times out, so the
+        // reactor has to keep the registration across timeouts.
+        udt::set_async_wait_timeout(Duration::from_millis(10));
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let writer = thread::spawn(move || {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            thread::sleep(Duration::from_millis(300));
+            connect.write_all(b"testing").expect("fail write()");
+            connect
+        });
+        block_on(async {
+            let (mut peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            let mut buf = [0; 7];
+            peer.read_exact(&mut buf).await.expect("fail read()");
+            assert_eq!(&buf, b"testing");
+        });
+        let connect = writer.join().expect("writer panicked");
+        assert!(connect.close().is_ok());
+        udt::set_async_wait_timeout(Duration::from_secs(1));
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_async_flush_loop() {
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let reader = thread::spawn(move || {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            let mut buf = vec![0; 1000 * 64];
+            connect.read_exact(&mut buf).expect("fail read()");
+            assert!(buf.iter().all(|&byte| byte == 7));
+            connect
+        });
+        block_on(async {
+            let (mut peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            for _ in 0..1000 {
+                peer.write_all(&[7; 64]).await.expect("fail write()");
+                peer.flush().await.expect("fail flush()");
+            }
+        });
+        let connect = reader.join().expect("reader panicked");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_recv_frame_too_large() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            connect.send_frame(b"testing").expect("fail send_frame()");
+            // A length prefix of 4 GiB with no body behind it.
+            connect.write_all(&[0xff; 4]).expect("fail write()");
+            connect
+        });
+        let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+        assert_eq!(peer.max_frame_size(), 16 * 1024 * 1024);
+        assert_eq!(peer.recv_frame().expect("fail recv_frame()"), b"testing");
+        match peer.recv_frame() {
+            Err(UdtError::LargeMsg(_)) => {}
+            other => panic!("expected LargeMsg, got {:?}", other),
+        }
+        let connect = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_multiplexer_addr() {
+        udt::startup().expect("failed startup");
+        let first = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        first.set_reuseaddr(true).expect("fail set_reuseaddr()");
+        let first = first
+            .bind("127.0.0.1:0".parse().unwrap())
+            .expect("fail bind()");
+        let addr = first.multiplexer_addr().expect("fail multiplexer_addr()");
+        let second = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        second.set_reuseaddr(true).expect("fail set_reuseaddr()");
+        let second = second.bind(addr).expect("fail bind()");
+        assert_eq!(
+            second.multiplexer_addr().expect("fail multiplexer_addr()"),
+            addr
+        );
+        // Without reuse_addr the port cannot be shared, so the bind fails.
+        let third = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        third.set_reuseaddr(false).expect("fail set_reuseaddr()");
+        assert!(third.bind(addr).is_err());
+        assert!(third.close().is_ok());
+        assert!(first.close().is_ok());
+        assert!(second.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_reuse_addr_listen() {
+        udt::startup().expect("failed startup");
+        let (first, addr) = udt::builder()
+            .set_reuse_addr(true)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        match udt::builder().set_reuse_addr(true).listen(addr, 1) {
+            Err(UdtError::DupListen(_)) => {}
+            Err(e) => panic!("expected DupListen, got {:?}", e),
+            Ok(_) => panic!("second listener on {} should fail", addr),
+        }
+        assert!(udt::builder()
+            .set_reuse_addr(false)
+            .listen(addr, 1)
+            .is_err());
+        // The first listener is unaffected.
+        let handle = thread::spawn(move || {
+            let (peer, _peer_addr) = first.accept().expect("fail accept()");
+            peer
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let peer = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_bind_tries_each_addr() {
+        udt::startup().expect("failed startup");
+        let (first, taken) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let addrs: Vec<SocketAddr> = vec![taken, "127.0.0.1:0".parse().unwrap()];
+        let (second, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr(&addrs[..], 1)
+            .expect("fail listen_with_addr()");
+        assert_ne!(addr, taken);
+        let bound = udt::builder()
+            .set_reuse_addr(false)
+            .bind(&addrs[..])
+            .expect("fail bind()");
+        assert_ne!(bound.local_addr().expect("fail local_addr()"), taken);
+        assert!(udt::builder()
+            .set_reuse_addr(false)
+            .bind(&addrs[..1])
+            .is_err());
+        assert!(first.close().is_ok());
+        assert!(second.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_socket_as_map_key() {
+        udt::startup().expect("failed startup");
+        let one = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        let two = udt::UdtSocket::new_ipv6().expect("fail new_ipv6()");
+        let mut states = std::collections::HashMap::new();
+        states.insert(one, "one");
+        states.insert(two, "two");
+        assert_eq!(states[&one], "one");
+        assert_eq!(states[&two], "two");
+        assert_eq!(one.cmp(&two), one.id.cmp(&two.id));
+        assert!(one.close().is_ok());
+        assert!(two.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_connect_falls_through() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        // Nothing listens on the IPv6 address, so connect has to move on to the
+        // IPv4 one.
+        let unused = udt::builder()
+            .set_reuse_addr(false)
+            .bind("[::1]:0")
+            .expect("fail bind()");
+        let dead = unused.local_addr().expect("fail local_addr()");
+        let addrs = [dead, addr];
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(&addrs[..])
+            .expect("fail connect()");
+        assert_eq!(connect.peer_addr().expect("fail peer_addr()"), addr);
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_server_shutdown_drains() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let (started_tx, started_rx) = mpsc::channel();
+        let started_tx = std::sync::Mutex::new(started_tx);
+        let server = listen.serve(move |mut peer, _peer_addr| {
+            started_tx.lock().unwrap().send(()).expect("fail send()");
+            let mut buf = [0; 7];
+            peer.read_exact(&mut buf).expect("fail read()");
+            peer.write_all(b"ok").expect("fail write()");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        started_rx.recv().expect("fail recv()");
+        // The transfer is still in progress when shutdown starts.
+        let client = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            connect.write_all(b"testing").expect("fail write()");
+            let mut buf = [0; 2];
+            connect.read_exact(&mut buf).expect("fail read()");
+            assert_eq!(&buf, b"ok");
+            connect
+        });
+        assert_eq!(
+            server
+                .shutdown(Duration::from_secs(5))
+                .expect("fail shutdown()"),
+            0
+        );
+        let connect = client.join().expect("client panicked");
+        assert!(udt::builder().set_reuse_addr(false).connect(addr).is_err());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_server_shutdown_aborts() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let (started_tx, started_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        let channels = std::sync::Mutex::new((started_tx, done_tx));
+        let server = listen.serve(move |mut peer, _peer_addr| {
+            let (started_tx, done_tx) = &*channels.lock().unwrap();
+            started_tx.send(()).expect("fail send()");
+            let mut buf = [0; 1];
+            let read = peer.read(&mut buf);
+            // The aborted socket is not closed a second time.
+            done_tx
+                .send((read.is_err(), peer.close()))
+                .expect("fail send()");
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        started_rx.recv().expect("fail recv()");
+        assert_eq!(
+            server
+                .shutdown(Duration::from_millis(100))
+                .expect("fail shutdown()"),
+            1
+        );
+        let (read_failed, closed) = done_rx.recv().expect("fail recv()");
+        assert!(read_failed);
+        assert!(closed.is_ok());
+        let _ = connect.close();
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_negotiated_flow_window() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .set_fc(2000)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_fc(1000)
+            .connect(addr)
+            .expect("fail connect()");
+        let window = connect
+            .negotiated_flow_window()
+            .expect("fail negotiated_flow_window()");
+        assert!(window > 0 && window <= 1000);
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_socket_connect_tries_all_addresses() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let unused = udt::builder()
+            .set_reuse_addr(false)
+            .bind("127.0.0.1:0")
+            .expect("fail bind()");
+        let dead = unused.local_addr().expect("fail local_addr()");
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        socket.connect(&[dead, addr][..]).expect("fail connect()");
+        assert_eq!(socket.peer_addr().expect("fail peer_addr()"), addr);
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        assert!(socket.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_buffered_small_messages() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let reader = thread::spawn(move || {
+            let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut lines = io::BufReader::new(peer);
+            let mut count = 0;
+            let mut line = String::new();
+            while count < 1000 {
+                line.clear();
+                io::BufRead::read_line(&mut lines, &mut line).expect("fail read_line()");
+                assert_eq!(line, "{\"message\":\"tiny\"}\n");
+                count += 1;
+            }
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let payload = connect
+            .packet_payload_size()
+            .expect("fail packet_payload_size()");
+        let mut writer = io::BufWriter::with_capacity(payload, udt::RecordingStream::new(connect));
+        for _ in 0..1000 {
+            writer
+                .write_all(b"{\"message\":\"tiny\"}\n")
+                .expect("fail write()");
+        }
+        writer.flush().expect("fail flush()");
+        let connect = writer.into_inner().expect("fail into_inner()");
+        let total = 1000 * 19;
+        assert!(connect.write_sizes().len() <= total / payload + 1);
+        reader.join().expect("reader panicked");
+        let writer = connect
+            .into_inner()
+            .into_buf_writer()
+            .expect("fail into_buf_writer()");
+        assert_eq!(writer.capacity(), payload);
+        drop(writer);
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_coalescing_stream() {
+        // Records the size and time of every write that reaches it.
+        #[derive(Clone, Default)]
+        struct MockWriter(std::sync::Arc<std::sync::Mutex<Vec<(usize, Instant)>>>);
+        impl Write for MockWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().push((buf.len(), Instant::now()));
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mock = MockWriter::default();
+        let delay = Duration::from_millis(50);
+        let mut stream = udt::CoalescingStream::new(mock.clone(), 1024, delay);
+        for _ in 0..1000 {
+            stream.write_all(b"abc").expect("fail write()");
+        }
+        let last_write = Instant::now();
+        thread::sleep(delay * 4);
+        assert_eq!(stream.buffered(), 0);
+        let writes = mock.0.lock().unwrap().clone();
+        assert_eq!(writes.iter().map(|(size, _)| size).sum::<usize>(), 3000);
+        assert!(writes.len() <= 4);
+        let (_, flushed_at) = writes.last().unwrap();
+        assert!(*flushed_at >= last_write && *flushed_at - last_write < delay * 3);
+        stream.write_all(b"tail").expect("fail write()");
+        stream.into_inner().expect("fail into_inner()");
+        assert_eq!(mock.0.lock().unwrap().last().unwrap().0, 4);
+    }
+
+    #[test]
+    fn test_coalescing_stream_write_error() {
+        // Takes at most 5 bytes per write and fails the second write.
+        #[derive(Clone, Default)]
+        struct FlakyWriter(std::sync::Arc<std::sync::Mutex<(Vec<u8>, usize)>>);
+        impl Write for FlakyWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let mut inner = self.0.lock().unwrap();
+                inner.1 += 1;
+                if inner.1 == 2 {
+                    return Err(io::Error::other("flaky"));
+                }
+                let len = buf.len().min(5);
+                inner.0.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let flaky = FlakyWriter::default();
+        let mut stream = udt::CoalescingStream::new(flaky.clone(), 8, Duration::from_secs(60));
+        // Accepted in full, even though flushing it fails halfway.
+        assert_eq!(stream.write(b"0123456789").expect("fail write()"), 10);
+        assert_eq!(stream.buffered(), 5);
+        // The failure is reported by the next call, which takes nothing.
+        assert!(stream.write(b"ab").is_err());
+        assert_eq!(stream.buffered(), 5);
+        stream.flush().expect("fail flush()");
+        stream.write_all(b"ab").expect("fail write()");
+        stream.into_inner().expect("fail into_inner()");
+        assert_eq!(flaky.0.lock().unwrap().0, b"0123456789ab");
+    }
+
+    #[test]
+    fn test_idle_duration() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            thread::sleep(Duration::from_millis(300));
+            peer.write_all(b"testing").expect("fail write()");
+            peer
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        thread::sleep(Duration::from_millis(100));
+        assert!(connect.idle_duration() >= Duration::from_millis(100));
+        let mut buf = [0; 7];
+        connect.read_exact(&mut buf).expect("fail read()");
+        assert!(connect.idle_duration() < Duration::from_millis(100));
+        let peer = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_stream_set_nonblocking() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (mut peer, _peer_addr) = handle.join().expect("fail join()");
+        connect
+            .set_nonblocking(true)
+            .expect("fail set_nonblocking()");
+        let mut buf = [0; 7];
+        let err = connect.read(&mut buf).expect_err("read should not block");
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        connect
+            .set_nonblocking(false)
+            .expect("fail set_nonblocking()");
+        peer.write_all(b"testing").expect("fail write()");
+        connect.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(&buf, b"testing");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_close_handshake() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let payload: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut received = vec![0; expected.len()];
+            peer.read_exact(&mut received).expect("fail read()");
+            assert!(received == expected);
+            peer.write_all(b"done").expect("fail write()");
+            peer.close_handshake(Duration::from_secs(5))
+                .expect("fail close_handshake()");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        connect.write_all(&payload).expect("fail write()");
+        let mut buf = [0; 4];
+        connect.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(&buf, b"done");
+        connect
+            .close_handshake(Duration::from_secs(5))
+            .expect("fail close_handshake()");
+        handle.join().expect("peer panicked");
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_peer_addr_after_break() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let local = connect.local_addr().expect("fail local_addr()");
+        let (mut peer, peer_addr) = handle.join().expect("fail join()");
+        assert_eq!(peer_addr, local);
+        assert!(connect.close().is_ok());
+        let mut buf = [0; 1];
+        // Returns once the close has been noticed.
+        let _ = peer.read(&mut buf);
+        assert!(peer.as_udt_socket().peer_addr().is_err());
+        assert_eq!(peer.peer_addr().expect("fail peer_addr()"), local);
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_stream_raw_roundtrip() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut buf = [0; 4];
+            peer.read_exact(&mut buf).expect("fail read()");
+            assert_eq!(&buf, b"test");
+            peer
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let id = connect.into_raw();
+        // Still open: dropping the stream did not close it.
+        assert!(matches!(
+            udt::UdtSocket::from_raw(id).get_state(),
+            udt::UdtStatus::Connected
+        ));
+        let mut connect = udt::UdtStream::from_raw(id);
+        assert_eq!(connect.peer_addr().expect("fail peer_addr()"), addr);
+        assert_eq!(connect.as_udt_socket().family(), udt::AddrFamily::Ipv4);
+        connect.write_all(b"test").expect("fail write()");
+        let peer = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_read_to_end() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let payload: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            peer.write_all(&payload).expect("fail write()");
+            assert!(peer.close().is_ok());
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        // The peer's close fails the read, but everything sent before it is kept.
+        let mut received = Vec::new();
+        let e = connect
+            .read_to_end(&mut received)
+            .expect_err("read_to_end should fail on ConnLost");
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(received == expected);
+        handle.join().expect("fail join()");
+        // The socket itself still reports the end of the connection as an error.
+        assert!(matches!(
+            connect.as_udt_socket().recv(&mut [0; 16]),
+            Err(UdtError::ConnLost(_))
+        ));
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_read_broken_link() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        // Relays packets between the client and the listener until cut, after
+        // which neither side hears from the other, as over a broken link.
+        let relay = std::net::UdpSocket::bind("127.0.0.1:0").expect("fail bind()");
+        let relay_addr = relay.local_addr().expect("fail local_addr()");
+        relay
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .expect("fail set_read_timeout()");
+        let cut = Arc::new(AtomicBool::new(false));
+        let relay_cut = cut.clone();
+        thread::spawn(move || {
+            let mut client = None;
+            let mut buf = [0; 2048];
+            while !relay_cut.load(Ordering::SeqCst) {
+                let (size, from) = match relay.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+                let to = if from == addr {
+                    match client {
+                        Some(client) => client,
+                        None => continue,
+                    }
+                } else {
+                    client = Some(from);
+                    addr
+                };
+                let _ = relay.send_to(&buf[..size], to);
+            }
+        });
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(relay_addr)
+            .expect("fail connect()");
+        let (mut peer, _peer_addr) = handle.join().expect("accept panicked");
+        peer.write_all(b"testing").expect("fail write()");
+        let mut buf = [0; 7];
+        connect.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(&buf, b"testing");
+
+        cut.store(true, Ordering::SeqCst);
+        let e = connect
+            .read(&mut buf)
+            .expect_err("a broken link should not read as end of stream");
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+        let inner = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UdtError>())
+            .expect("io::Error lost the UdtError");
+        assert!(matches!(inner, UdtError::ConnLost(_)));
+        let _ = connect.close();
+        let _ = peer.close();
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_async_peer_addr_after_break() {
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let connect = thread::spawn(move || {
+            udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()")
+        });
+        block_on(async {
+            let (mut peer, peer_addr) = listen.accept().await.expect("fail accept()");
+            let connect = connect.join().expect("fail join()");
+            let local = connect.local_addr().expect("fail local_addr()");
+            assert_eq!(peer_addr, local);
+            assert_eq!(peer.peer_addr().expect("fail peer_addr()"), local);
+            assert!(connect.close().is_ok());
+            let mut buf = [0; 1];
+            // Returns once the close has been noticed.
+            let _ = peer.read(&mut buf).await;
+            assert!(peer.as_udt_socket().peer_addr().is_err());
+            assert_eq!(peer.peer_addr().expect("fail peer_addr()"), local);
+        });
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_async_drop_does_not_linger() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .set_rcv_buf(64 * 1024)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(8 * 1024 * 1024)
+            .set_linger(180)
+            .connect(addr)
+            .expect("fail connect()");
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        // The peer never reads, so most of this stays in the send buffer.
+        connect
+            .write_all(&vec![0; 4 * 1024 * 1024])
+            .expect("fail write()");
+        let connect = udt::UdtAsyncStream::from_blocking(connect).expect("fail from_blocking()");
+        connect
+            .as_udt_socket()
+            .set_sndsyn(true)
+            .expect("fail set_sndsyn()");
+        assert!(connect.as_udt_socket().get_snddata().expect("fail snddata") > 0);
+        let started = Instant::now();
+        drop(connect);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(peer.abort().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_async_read_timeout() {
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            rx.recv().expect("fail recv()");
+            connect.write_all(b"test").expect("fail write()");
+            connect
+        });
+        block_on(async {
+            let (mut peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            let mut buf = [0; 4];
+            let started = Instant::now();
+            let read = peer
+                .read_timeout(&mut buf, Duration::from_millis(200))
+                .await
+                .expect("fail read_timeout()");
+            assert_eq!(read, None);
+            assert!(started.elapsed() >= Duration::from_millis(200));
+            tx.send(()).expect("fail send()");
+            let read = peer
+                .read_timeout(&mut buf, Duration::from_secs(5))
+                .await
+                .expect("fail read_timeout()");
+            assert!(read.expect("read timed out") > 0);
+        });
+        let connect = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_poll_write_ready() {
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let connect = thread::spawn(move || {
+            udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()")
+        });
+        block_on(async {
+            let (peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            future::poll_fn(|cx| peer.poll_write_ready(cx))
+                .await
+                .expect("fail poll_write_ready()");
+        });
+        let connect = connect.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_poll_write_ready_full() {
+        use futures::io::AsyncWrite;
+        use std::{
+            pin::Pin,
+            task::{Context, Poll},
+        };
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .set_rcv_buf(1)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (_peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = block_on(
+            udt::async_builder()
+                .set_reuse_addr(false)
+                .set_snd_buf(64 * 1472)
+                .connect(addr)
+                .expect("fail start connect"),
+        )
+        .expect("fail connect");
+        let socket = connect.as_udt_socket();
+        let mss = connect.get_mss().expect("fail get_mss()");
+        let capacity = connect.get_snd_buf().expect("fail get_snd_buf()") / (mss - 28);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let chunk = vec![0; 64 * 1024];
+        // Keep writing until the peer stops acknowledging and the buffer stays full.
+        let start = Instant::now();
+        while socket.get_snddata().expect("fail get_snddata()") < capacity {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            while let Poll::Ready(result) = Pin::new(&mut connect).poll_write(&mut cx, &chunk) {
+                result.expect("fail poll_write()");
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(connect.poll_write_ready(&mut cx).is_pending());
+        tx.send(()).expect("fail send through mpsc channel");
+        drop(connect);
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_abort() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_linger(180)
+            .connect(addr)
+            .expect("fail connect()");
+        let (mut peer, _peer_addr) = handle.join().expect("fail join()");
+        connect.write_all(&[0; 64 * 1024]).expect("fail write()");
+        let started = Instant::now();
+        connect.abort().expect("fail abort()");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        let mut buf = [0; 1024];
+        while let Ok(size) = peer.read(&mut buf) {
+            assert!(size > 0);
+        }
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_byte_counters() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (mut peer, _peer_addr) = handle.join().expect("fail join()");
+        for _ in 0..3 {
+            connect.write_all(b"testing").expect("fail write()");
+        }
+        let mut buf = [0; 21];
+        peer.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(connect.bytes_sent(), 21);
+        assert_eq!(connect.bytes_received(), 0);
+        assert_eq!(peer.bytes_received(), 21);
+        assert_eq!(peer.bytes_sent(), 0);
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_try_accept() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        listen
+            .set_nonblocking(true)
+            .expect("fail set_nonblocking()");
+        assert!(listen.try_accept().expect("fail try_accept()").is_none());
+        let handle = thread::spawn(move || {
+            udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()")
+        });
+        let (peer, _peer_addr) = loop {
+            match listen.try_accept().expect("fail try_accept()") {
+                Some(accepted) => break accepted,
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        assert!(peer
+            .as_udt_socket()
+            .get_rcvsyn()
+            .expect("fail get_rcvsyn()"));
+        let connect = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_listener_shutdown() {
+        udt::startup().expect("failed startup");
+        let listen = std::sync::Arc::new(
+            udt::builder()
+                .set_reuse_addr(false)
+                .listen("127.0.0.1:0", 1)
+                .expect("fail listen()"),
+        );
+        let handle = {
+            let listen = listen.clone();
+            thread::spawn(move || listen.accept().map(|_| ()))
+        };
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        listen.shutdown().expect("fail shutdown()");
+        assert!(handle.join().expect("fail join()").is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(listen.accept().is_err());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_listener_pending() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 4)
+            .expect("fail listen_with_addr()");
+        assert_eq!(listen.pending().expect("fail pending()"), 0);
+        let connects: Vec<udt::UdtStream> = (0..2)
+            .map(|_| {
+                udt::builder()
+                    .set_reuse_addr(false)
+                    .connect(addr)
+                    .expect("fail connect()")
+            })
+            .collect();
+        assert_eq!(listen.pending().expect("fail pending()"), 2);
+        for connect in &connects {
+            let (peer, peer_addr) = listen.accept().expect("fail accept()");
+            let local = connect.local_addr().expect("fail local_addr()");
+            assert_eq!(peer_addr.port(), local.port());
+            assert!(peer
+                .as_udt_socket()
+                .get_rcvsyn()
+                .expect("fail get_rcvsyn()"));
+            assert!(peer.close().is_ok());
+        }
+        assert_eq!(listen.pending().expect("fail pending()"), 0);
+        for connect in connects {
+            assert!(connect.close().is_ok());
+        }
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_socket_pool() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 2)
+            .expect("fail listen_with_addr()");
+        let pool = udt::builder()
+            .set_reuse_addr(false)
+            .socket_pool(udt::AddrFamily::Ipv4, 2)
+            .expect("fail socket_pool()");
+        assert_eq!(pool.idle(), 2);
+        let handle = thread::spawn(move || {
+            for _ in 0..3 {
+                let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+                let mut buf = [0; 7];
+                peer.read_exact(&mut buf).expect("fail read()");
+                assert_eq!(&buf, b"testing");
+            }
+        });
+        for idle in [1, 0, 0].iter() {
+            let mut connect = pool.connect(addr).expect("fail connect()");
+            assert_eq!(pool.idle(), *idle);
+            connect.write_all(b"testing").expect("fail write()");
+            connect
+                .close_gracefully(1)
+                .expect("fail close_gracefully()");
+        }
+        handle.join().expect("fail join()");
+        pool.fill(1).expect("fail fill()");
+        assert_eq!(pool.idle(), 1);
+        drop(pool);
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_connection_info() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_mss(1200)
+            .set_max_bw(1_000_000)
+            .connect(addr)
+            .expect("fail connect()");
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        let info = connect.info().expect("fail info()");
+        assert_eq!(info.peer_addr, addr);
+        assert_eq!(
+            info.local_addr,
+            connect.local_addr().expect("fail local_addr()")
+        );
+        assert!(matches!(info.state, udt::UdtStatus::Connected));
+        assert_eq!(info.mss, 1200);
+        assert_eq!(info.max_bw, 1_000_000);
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_transport() {
+        fn echo_once(transport: &dyn udt::Transport) {
+            let listener = transport
+                .listen("127.0.0.1:0".parse().unwrap())
+                .expect("fail listen()");
+            let addr = listener.local_addr().expect("fail local_addr()");
+            let server = thread::spawn(move || {
+                let (mut conn, _peer_addr) = listener.accept().expect("fail accept()");
+                let mut buf = [0; 7];
+                conn.read_exact(&mut buf).expect("fail read()");
+                conn.write_all(&buf).expect("fail write()");
+                conn
+            });
+            let mut conn = transport.connect(addr).expect("fail connect()");
+            assert_eq!(conn.peer_addr().expect("fail peer_addr()"), addr);
+            conn.write_all(b"testing").expect("fail write()");
+            let mut buf = [0; 7];
+            conn.read_exact(&mut buf).expect("fail read()");
+            assert_eq!(&buf, b"testing");
+            let peer = server.join().expect("fail join()");
+            conn.close().expect("fail close()");
+            peer.close().expect("fail close()");
+        }
+        udt::startup().expect("failed startup");
+        echo_once(&udt::TcpTransport);
+        echo_once(&udt::builder().set_reuse_addr(false));
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_write_buffered() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let payload: Vec<u8> = (0..4_000_000).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut received = vec![0; expected.len()];
+            peer.read_exact(&mut received).expect("fail read()");
+            assert!(received == expected);
+            peer
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(256 * 1024)
+            .connect(addr)
+            .expect("fail connect()");
+        let started = Instant::now();
+        let written = connect
+            .write_buffered(&payload)
+            .expect("fail write_buffered()");
+        assert_eq!(written, payload.len());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(connect.buffered_len() > 0);
+        connect.flush().expect("fail flush()");
+        assert_eq!(connect.buffered_len(), 0);
+        assert_eq!(connect.bytes_sent(), payload.len() as u64);
+        let peer = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_write_buffered_full() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .set_rcv_buf(1)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (_peer, _peer_addr) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(64 * 1472)
+            .connect(addr)
+            .expect("fail connect()");
+        // A send that blocked on the full buffer would fail with Timeout
+        // instead of hanging the test.
+        connect
+            .set_snd_timeout(1000)
+            .expect("fail set_snd_timeout()");
+        let chunk = vec![0; 64 * 1024];
+        for _ in 0..32 {
+            connect
+                .write_buffered(&chunk)
+                .expect("fail write_buffered()");
+        }
+        assert!(connect.buffered_len() > 0);
+        assert!(connect.drain_buffered().is_ok());
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(connect.abort().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_flush_acked() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut received = vec![0; 1_000_000];
+            peer.read_exact(&mut received).expect("fail read()");
+            peer
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        connect
+            .write_buffered(&vec![7; 1_000_000])
+            .expect("fail write_buffered()");
+        connect
+            .flush_acked(Duration::from_secs(10))
+            .expect("fail flush_acked()");
+        assert_eq!(connect.buffered_len(), 0);
+        assert_eq!(connect.packets_buffered_to_send().expect("fail snddata"), 0);
+        let peer = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        connect
+            .flush_acked(Duration::from_secs(1))
+            .expect("fail flush_acked() with nothing to send");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_socket_send_all_recv_exact() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let payload: Vec<u8> = (0..2_000_000).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let handle = thread::spawn(move || {
+            let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut received = vec![0; expected.len()];
+            peer.as_udt_socket()
+                .recv_exact(&mut received)
+                .expect("fail recv_exact()");
+            assert!(received == expected);
+            peer
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        connect
+            .as_udt_socket()
+            .send_all(&payload)
+            .expect("fail send_all()");
+        let peer = handle.join().expect("peer panicked");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_socket_send_from() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let payload: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
+        let expected = payload[..700_000].to_vec();
+        let handle = thread::spawn(move || {
+            let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut received = vec![0; expected.len()];
+            peer.as_udt_socket()
+                .recv_exact(&mut received)
+                .expect("fail recv_exact()");
+            assert!(received == expected);
+            peer
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        // Chained readers hand out short reads at the seam.
+        let (head, tail) = payload.split_at(300_000);
+        let mut reader = head.chain(tail);
+        let sent = connect
+            .as_udt_socket()
+            .send_from(&mut reader, 700_000)
+            .expect("fail send_from()");
+        assert_eq!(sent, 700_000);
+        let peer = handle.join().expect("peer panicked");
+        let mut short: &[u8] = &[1, 2, 3];
+        let sent = connect
+            .as_udt_socket()
+            .send_from(&mut short, 10)
+            .expect("fail send_from()");
+        assert_eq!(sent, 3);
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_datagram_max_message_size() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen_datagram("127.0.0.1:0", 1)
+            .expect("fail listen_datagram()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let handle = thread::spawn(move || {
+            let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut buf = vec![0; 1024 * 1024];
+            let size = peer.recv(&mut buf).expect("fail recv()");
+            (peer, size)
+        });
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_snd_buf(64 * 1024)
+            .connect_datagram(addr)
+            .expect("fail connect_datagram()");
+        let max = connect.max_message_size().expect("fail max_message_size()");
+        assert!(max > 0 && max <= 64 * 1024);
+        match connect.send(&vec![0; max + 1]) {
+            Err(UdtError::LargeMsg(_)) => {}
+            other => panic!("expected LargeMsg, got {:?}", other),
+        }
+        assert_eq!(connect.send(&vec![0; max]).expect("fail send()"), max);
+        let (peer, size) = handle.join().expect("fail join()");
+        assert_eq!(size, max);
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_datagram_recv_vectored() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen_datagram("127.0.0.1:0", 1)
+            .expect("fail listen_datagram()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let handle = thread::spawn(move || {
+            let connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect_datagram(addr)
+                .expect("fail connect_datagram()");
+            connect.send(b"headbody").expect("fail send()");
+            connect.send(b"far too long").expect("fail send()");
+            connect
+        });
+        let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+        let mut header = [0; 4];
+        let mut body = [0; 6];
+        let size = peer
+            .recv_vectored(&mut [
+                io::IoSliceMut::new(&mut header),
+                io::IoSliceMut::new(&mut body),
+            ])
+            .expect("fail recv_vectored()");
+        assert_eq!(size, 8);
+        assert_eq!(&header, b"head");
+        assert_eq!(&body[..4], b"body");
+        match peer.recv_vectored(&mut [
+            io::IoSliceMut::new(&mut header),
+            io::IoSliceMut::new(&mut body),
+        ]) {
+            Err(UdtError::LargeMsg(_)) => {}
+            other => panic!("expected LargeMsg, got {:?}", other),
+        }
+        let connect = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_datagram_boundaries() {
+        udt::startup().expect("failed startup");
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .listen_datagram("127.0.0.1:0", 1)
+            .expect("fail listen_datagram()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let handle = thread::spawn(move || {
+            let connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect_datagram(addr)
+                .expect("fail connect_datagram()");
+            for msg in &[&b"one"[..], &b"three"[..], &[7; 5000][..], &b"too long"[..]] {
+                assert_eq!(connect.send(msg).expect("fail send()"), msg.len());
+            }
+            connect
+        });
+        let (peer, _peer_addr) = listen.accept().expect("fail accept()");
+        let mut buf = [0; 5000];
+        assert_eq!(peer.recv(&mut buf).expect("fail recv()"), 3);
+        assert_eq!(&buf[..3], b"one");
+        assert_eq!(peer.recv(&mut buf).expect("fail recv()"), 5);
+        assert_eq!(&buf[..5], b"three");
+        assert_eq!(peer.recv(&mut buf).expect("fail recv()"), 5000);
+        assert!(buf.iter().all(|&byte| byte == 7));
+        match peer.recv(&mut buf[..4]) {
+            Err(UdtError::LargeMsg(_)) => {}
+            other => panic!("expected LargeMsg, got {:?}", other),
+        }
+        let connect = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_check_alive() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        assert!(connect.check_alive().expect("fail check_alive()"));
+        assert!(peer.close().is_ok());
+        let start = Instant::now();
+        while connect.check_alive().expect("fail check_alive()") {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_bind_then_connect() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let local = std::net::UdpSocket::bind("127.0.0.2:0")
+            .and_then(|socket| socket.local_addr())
+            .expect("fail to reserve a port");
+        let bound = udt::builder()
+            .set_reuse_addr(false)
+            .bind(local)
+            .expect("fail bind()");
+        assert_eq!(bound.local_addr().expect("fail local_addr()"), local);
+        let connect = bound.connect(addr).expect("fail connect()");
+        assert_eq!(connect.local_addr().expect("fail local_addr()"), local);
+        let (peer, peer_addr) = handle.join().expect("fail join()");
+        assert_eq!(peer_addr, local);
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_close_timeout() {
+        udt::startup().expect("failed startup");
+        // The peer never reads, so unacknowledged data would keep close waiting.
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .set_rcv_buf(1)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (_peer, _) = listen.accept().expect("fail accept()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        connect
+            .write_all(&vec![0; 1_000_000])
+            .expect("fail write()");
+        let start = Instant::now();
+        connect
+            .close_timeout(Duration::from_millis(500))
+            .expect("fail close_timeout()");
+        assert!(start.elapsed() < Duration::from_secs(3));
+        tx.send(()).expect("fail send through mpsc channel");
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_stream_option_getters() {
+        udt::startup().expect("failed startup");
+        let free_addr = |ip: &str| {
+            std::net::UdpSocket::bind((ip, 0))
+                .and_then(|socket| socket.local_addr())
+                .expect("fail to reserve a port")
+        };
+        let one_addr = free_addr("127.0.0.1");
+        let two_addr = free_addr("127.0.0.2");
+        let handle = thread::spawn(move || {
+            udt::builder()
+                .set_reuse_addr(false)
+                .rendezvous(one_addr, two_addr)
+                .expect("fail rendezvous()")
+        });
+        let two = udt::builder()
+            .set_reuse_addr(false)
+            .set_mss(1400)
+            .set_rcv_timeo(1234)
+            .rendezvous(two_addr, one_addr)
+            .expect("fail rendezvous()");
+        assert!(two.is_rendezvous().expect("fail is_rendezvous()"));
+        assert_eq!(two.get_mss().expect("fail get_mss()"), 1400);
+        assert_eq!(two.get_rcv_timeout().expect("fail get_rcv_timeout()"), 1234);
+        assert!(!two.get_reuse_addr().expect("fail get_reuse_addr()"));
+        assert!(matches!(two.get_state(), udt::UdtStatus::Connected));
+        let one = handle.join().expect("fail join()");
+        assert!(one.close().is_ok());
+        assert!(two.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_accept_many() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 8)
+            .expect("fail listen_with_addr()");
+        assert!(listen
+            .accept_many(4)
+            .expect("fail accept_many()")
+            .is_empty());
+        let connects: Vec<_> = (0..3)
+            .map(|_| {
+                udt::builder()
+                    .set_reuse_addr(false)
+                    .connect(addr)
+                    .expect("fail connect()")
+            })
+            .collect();
+        let mut accepted = Vec::new();
+        let start = Instant::now();
+        while accepted.len() < 3 && start.elapsed() < Duration::from_secs(5) {
+            accepted.extend(listen.accept_many(2).expect("fail accept_many()"));
+        }
+        assert_eq!(accepted.len(), 3);
+        // The accepted streams block again, as the listener does.
+        for (peer, _peer_addr) in &accepted {
+            assert!(peer
+                .as_udt_socket()
+                .get_rcvsyn()
+                .expect("fail get_rcvsyn()"));
+        }
+        assert!(listen.socket.get_rcvsyn().expect("fail get_rcvsyn()"));
+        for connect in connects {
+            assert!(connect.close().is_ok());
+        }
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_pending_leaves_blocking_accept() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 4)
+            .expect("fail listen_with_addr()");
+        let other = listen.try_clone().expect("fail try_clone()");
+        let handle = thread::spawn(move || other.accept().map(|(peer, _)| peer));
+        thread::sleep(Duration::from_millis(100));
+        for _ in 0..10 {
+            assert_eq!(listen.pending().expect("fail pending()"), 0);
+            assert!(listen
+                .accept_many(4)
+                .expect("fail accept_many()")
+                .is_empty());
+        }
+        assert!(listen.socket.get_rcvsyn().expect("fail get_rcvsyn()"));
+        // The other handle is still blocked in accept and gets the connection.
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let peer = handle
+            .join()
+            .expect("accept panicked")
+            .expect("fail accept()");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_listener_try_clone() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 4)
+            .expect("fail listen_with_addr()");
+        let acceptors: Vec<_> = (0..2)
+            .map(|_| {
+                let listen = listen.try_clone().expect("fail try_clone()");
+                thread::spawn(move || listen.accept().expect("fail accept()"))
+            })
+            .collect();
+        // The clones keep the socket open after the original is gone.
+        drop(listen);
+        let connects: Vec<_> = (0..2)
+            .map(|_| {
+                udt::builder()
+                    .set_reuse_addr(false)
+                    .connect(addr)
+                    .expect("fail connect()")
+            })
+            .collect();
+        for acceptor in acceptors {
+            let (peer, _peer_addr) = acceptor.join().expect("acceptor panicked");
+            assert!(peer.close().is_ok());
+        }
+        for connect in connects {
+            assert!(connect.close().is_ok());
+        }
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_recv_to_vec() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            peer.write_all(b"testing").expect("fail write()");
+            thread::sleep(Duration::from_millis(200));
+            assert!(peer.close().is_ok());
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        assert_eq!(connect.recv_to_vec(4).expect("fail recv_to_vec()"), b"test");
+        let rest = connect.recv_to_vec(1024).expect("fail recv_to_vec()");
+        assert_eq!(rest, b"ing");
+        assert_eq!(rest.capacity(), 3);
+        handle.join().expect("peer panicked");
+        assert!(matches!(
+            connect.recv_to_vec(1024),
+            Err(UdtError::ConnLost(_))
+        ));
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_recv_to_vec_buffered_packets() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let (tx, rx) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let payload = peer
+                .packet_payload_size()
+                .expect("fail packet_payload_size()");
+            peer.write_all(&vec![7; 3 * payload]).expect("fail write()");
+            rx.recv().expect("fail recv through mpsc channel");
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let start = Instant::now();
+        while connect
+            .packets_available_to_read()
+            .expect("fail get_rcvdata()")
+            < 3
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let payload = connect
+            .packet_payload_size()
+            .expect("fail packet_payload_size()");
+        let received = connect.recv_to_vec(usize::MAX).expect("fail recv_to_vec()");
+        assert_eq!(received.len(), 3 * payload);
+        tx.send(()).expect("fail send through mpsc channel");
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_mss_validation() {
+        udt::startup().expect("failed startup");
+        match udt::builder().set_mss(28).listen("127.0.0.1:0", 1) {
+            Err(UdtError::InvParam(_)) => {}
+            Err(e) => panic!("expected InvParam, got {:?}", e),
+            Ok(_) => panic!("an MSS of 28 was accepted"),
+        }
+        match udt::builder()
+            .set_mss(udt::MIN_MSS - 1)
+            .listen("127.0.0.1:0", 1)
+        {
+            Err(UdtError::InvParam(_)) => {}
+            Err(e) => panic!("expected InvParam, got {:?}", e),
+            Ok(_) => panic!("an MSS below MIN_MSS was accepted"),
+        }
+        let listen = udt::builder()
+            .set_reuse_addr(false)
+            .set_mss(udt::MIN_MSS)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        assert_eq!(
+            listen.socket.get_mss().expect("fail get_mss()"),
+            udt::MIN_MSS
+        );
+        assert!(listen.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_connect_future_drop() {
+        udt::startup().expect("failed startup");
+        // Bound but never listening, so the connect stays pending.
+        let unused = udt::builder()
+            .set_reuse_addr(false)
+            .bind("127.0.0.1:0")
+            .expect("fail bind()");
+        let dead = unused.local_addr().expect("fail local_addr()");
+        let connect = udt::async_builder()
+            .set_reuse_addr(false)
+            .bind("127.0.0.1:0")
+            .expect("fail bind()")
+            .connect(dead)
+            .expect("fail start connect");
+        let socket = connect.socket;
+        assert!(matches!(socket.get_state(), udt::UdtStatus::Connecting));
+        drop(connect);
+        assert!(matches!(
+            socket.get_state(),
+            udt::UdtStatus::Closed | udt::UdtStatus::NonExist
+        ));
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_async_connect_timeout() {
+        udt::startup().expect("failed startup");
+        // Bound but never listening, so the handshake gets no answer.
+        let unused = udt::builder()
+            .set_reuse_addr(false)
+            .bind("127.0.0.1:0")
+            .expect("fail bind()");
+        let dead = unused.local_addr().expect("fail local_addr()");
+        let connect = udt::async_builder()
+            .set_reuse_addr(false)
+            .bind("127.0.0.1:0")
+            .expect("fail bind()")
+            .connect_timeout(dead, Duration::from_millis(300))
+            .expect("fail start connect");
+        let start = Instant::now();
+        match block_on(connect) {
+            Err(UdtError::Timeout(_)) => {}
+            Err(e) => panic!("expected Timeout, got {:?}", e),
+            Ok(_) => panic!("connected to a socket that is not listening"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_selector() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 2)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let (one, _) = listen.accept().expect("fail accept()");
+            let (two, _) = listen.accept().expect("fail accept()");
+            (one, two)
+        });
+        let connects: Vec<_> = (0..2)
+            .map(|_| {
+                udt::builder()
+                    .set_reuse_addr(false)
+                    .connect(addr)
+                    .expect("fail connect()")
+            })
+            .collect();
+        let (one, mut two) = handle.join().expect("fail join()");
+        let mut selector = udt::UdtSelector::new().expect("fail new()");
+        for connect in &connects {
+            selector
+                .register(connect.as_udt_socket(), udt::Interest::Read)
+                .expect("fail register()");
+        }
+        let ready = selector
+            .select(Some(Duration::from_millis(100)))
+            .expect("fail select()");
+        assert!(ready.is_empty());
+        two.write_all(b"testing").expect("fail write()");
+        let ready = selector
+            .select(Some(Duration::from_secs(5)))
+            .expect("fail select()");
+        assert_eq!(ready.len(), 1);
+        let (socket, readiness) = ready[0];
+        assert!(readiness.readable && !readiness.writable);
+        assert_eq!(socket.local_addr().unwrap(), two.peer_addr().unwrap());
+        selector
+            .register(socket, udt::Interest::ReadWrite)
+            .expect("fail register()");
+        let ready = selector.select(None).expect("fail select()");
+        assert!(ready.contains(&(
+            socket,
+            udt::Readiness {
+                readable: true,
+                writable: true,
+                error: false,
+            }
+        )));
+        for connect in connects {
+            selector
+                .deregister(connect.as_udt_socket())
+                .expect("fail deregister()");
+            assert!(connect.close().is_ok());
+        }
+        assert!(one.close().is_ok());
+        assert!(two.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_selector_errors() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        let mut selector = udt::UdtSelector::new().expect("fail new()");
+        selector
+            .register(peer.as_udt_socket(), udt::Interest::Error)
+            .expect("fail register()");
+        assert!(selector.contains(&peer.as_udt_socket()));
+        assert!(!selector.contains(&connect.as_udt_socket()));
+        assert_eq!(selector.num_read_sockets(), 1);
+        assert_eq!(selector.num_write_sockets(), 1);
+        let ready = selector
+            .select(Some(Duration::from_millis(100)))
+            .expect("fail select()");
+        assert!(ready.is_empty());
+        assert!(connect.close().is_ok());
+        let ready = selector
+            .select(Some(Duration::from_secs(5)))
+            .expect("fail select()");
+        assert_eq!(
+            ready,
+            vec![(
+                peer.as_udt_socket(),
+                udt::Readiness {
+                    readable: false,
+                    writable: false,
+                    error: true,
+                }
+            )]
+        );
+        selector
+            .deregister(peer.as_udt_socket())
+            .expect("fail deregister()");
+        assert!(!selector.contains(&peer.as_udt_socket()));
+        assert_eq!(selector.num_read_sockets(), 0);
+        assert_eq!(selector.num_write_sockets(), 0);
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_accept_with_filter() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 2)
+            .expect("fail listen_with_addr()");
+        let banned = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let banned_addr = banned.local_addr().expect("fail local_addr()");
+        let allowed = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        let (peer, peer_addr) = listen
+            .accept_with_filter(|peer_addr| peer_addr != banned_addr)
+            .expect("fail accept_with_filter()");
+        assert_eq!(peer_addr, allowed.local_addr().expect("fail local_addr()"));
+        let start = Instant::now();
+        while banned.check_alive().expect("fail check_alive()") {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(peer.close().is_ok());
+        assert!(allowed.close().is_ok());
+        assert!(banned.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_raw_socket_options() {
+        udt::startup().expect("failed startup");
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        unsafe {
+            socket
+                .set_opt_raw(udt_sys::UDTOpt::UDT_MSS, &1400i32.to_ne_bytes())
+                .expect("fail set_opt_raw()");
+        }
+        assert_eq!(socket.get_mss().expect("fail get_mss()"), 1400);
+        let mut buf = [0; 8];
+        let len = unsafe {
+            socket
+                .get_opt_raw(udt_sys::UDTOpt::UDT_MSS, &mut buf)
+                .expect("fail get_opt_raw()")
+        };
+        assert_eq!(len, 4);
+        assert_eq!(i32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]), 1400);
+        assert!(socket.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_into_blocking() {
+        udt::startup().expect("failed startup");
+        let listen = udt::async_builder()
+            .set_reuse_addr(false)
+            .listen("127.0.0.1:0", 1)
+            .expect("fail listen()");
+        let addr = listen.local_addr().expect("fail local_addr()");
+        let handle = thread::spawn(move || {
+            let mut connect = udt::builder()
+                .set_reuse_addr(false)
+                .connect(addr)
+                .expect("fail connect()");
+            connect.write_all(b"testing").expect("fail write()");
+            let mut buf = [0; 4];
+            connect.read_exact(&mut buf).expect("fail read()");
+            assert_eq!(&buf, b"done");
+            connect
+        });
+        let peer = block_on(async {
+            let (mut peer, _peer_addr) = listen.accept().await.expect("fail accept()");
+            let mut buf = [0; 4];
+            peer.read_exact(&mut buf).await.expect("fail read()");
+            assert_eq!(&buf, b"test");
+            peer
+        });
+        let mut peer = peer.into_blocking().expect("fail into_blocking()");
+        let mut buf = [0; 3];
+        peer.read_exact(&mut buf).expect("fail read()");
+        assert_eq!(&buf, b"ing");
+        peer.write_all(b"done").expect("fail write()");
+        let connect = handle.join().expect("fail join()");
+        assert!(connect.close().is_ok());
+        assert!(peer.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_from_blocking() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || {
+            let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+            let mut buf = [0; 7];
+            peer.read_exact(&mut buf).expect("fail read()");
+            assert_eq!(&buf, b"testing");
+            peer.write_all(b"done").expect("fail write()");
+            peer
+        });
+        let mut connect = udt::builder()
+            .set_reuse_addr(false)
+            .connect(addr)
+            .expect("fail connect()");
+        connect
+            .write_buffered(b"test")
+            .expect("fail write_buffered()");
+        let mut connect =
+            udt::UdtAsyncStream::from_blocking(connect).expect("fail from_blocking()");
+        assert_eq!(connect.peer_addr().expect("fail peer_addr()"), addr);
+        block_on(async {
+            connect.write_all(b"ing").await.expect("fail write()");
+            let mut buf = [0; 4];
+            connect.read_exact(&mut buf).await.expect("fail read()");
+            assert_eq!(&buf, b"done");
+        });
+        let peer = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        drop(connect);
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_fc_applied_before_buffers() {
+        udt::startup().expect("failed startup");
+        let builder = udt::builder().set_rcv_buf(64 * 1024 * 1024).set_fc(100_000);
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        builder
+            .config_socket(&socket)
+            .expect("fail config_socket()");
+        assert_eq!(socket.get_fc().expect("fail get_fc()"), 100_000);
+        // Set under the default window of 25600 packets, it would be capped there.
+        let mss = socket.get_mss().expect("fail get_mss()");
+        assert!(socket.get_rcvbuf().expect("fail get_rcvbuf()") > 25600 * (mss - 28));
+        assert!(socket.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_options_applied_in_canonical_order() {
+        udt::startup().expect("failed startup");
+        let buf_size = 1024 * 1024;
+        // The reverse of the order UDT needs.
+        let builder = udt::builder()
+            .set_snd_buf(buf_size)
+            .set_rcv_buf(buf_size)
+            .set_fc(4096)
+            .set_mss(600)
+            .set_mss(700);
+        assert_eq!(builder.options()[0], udt::UdtSockOpt::SndBuf(buf_size));
+        let socket = udt::UdtSocket::new_ipv4().expect("fail new_ipv4()");
+        builder
+            .config_socket(&socket)
+            .expect("fail config_socket()");
+        assert_eq!(socket.get_mss().expect("fail get_mss()"), 700);
+        assert_eq!(socket.get_fc().expect("fail get_fc()"), 4096);
+        // Sizes are kept in whole packets of MSS - 28 bytes.
+        let packet = 700 - 28;
+        let sndbuf = socket.get_sndbuf().expect("fail get_sndbuf()");
+        assert!(sndbuf <= buf_size && sndbuf > buf_size - packet);
+        let rcvbuf = socket.get_rcvbuf().expect("fail get_rcvbuf()");
+        assert!(rcvbuf <= buf_size && rcvbuf > buf_size - packet);
+        assert!(socket.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
+
+    #[test]
+    fn test_effective_config() {
+        udt::startup().expect("failed startup");
+        let (listen, addr) = udt::builder()
+            .set_reuse_addr(false)
+            .set_mss(1200)
+            .listen_with_addr("127.0.0.1:0", 1)
+            .expect("fail listen_with_addr()");
+        let handle = thread::spawn(move || listen.accept().expect("fail accept()"));
+        let connect = udt::builder()
+            .set_reuse_addr(false)
+            .set_fc(4096)
+            .connect(addr)
+            .expect("fail connect()");
+        let config = connect.effective_config().expect("fail effective_config()");
+        // The smaller MSS of the two sides wins the handshake.
+        assert_eq!(config.mss, 1200);
+        assert_eq!(config.mss, connect.get_mss().expect("fail get_mss()"));
+        assert_eq!(config.flight_flag_size, 4096);
+        assert_eq!(
+            config.flight_flag_size,
+            connect
+                .get_flight_flag_size()
+                .expect("fail get_flight_flag_size()")
+        );
+        assert!(!config.rendezvous);
+        assert!(!config.reuse_addr);
+        let (peer, _peer_addr) = handle.join().expect("fail join()");
+        assert!(peer.close().is_ok());
+        assert!(connect.close().is_ok());
+        udt::cleanup().expect("failed cleanup()");
+    }
 }
@@ -0,0 +1,94 @@
+use crate::{
+    close_on_drop, close_on_err, error::UdtError, AddrFamily, UdtBuilder, UdtSocket, UdtStream,
+};
+
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Mutex,
+};
+
+type Result<T> = std::result::Result<T, UdtError>;
+
+impl UdtBuilder {
+    /// Creates a pool holding `size` sockets configured with this builder's
+    /// options, ready for outgoing connections to `family` addresses.
+    pub fn socket_pool(self, family: AddrFamily, size: usize) -> Result<UdtSocketPool> {
+        let pool = UdtSocketPool {
+            builder: self,
+            family,
+            idle: Mutex::new(Vec::with_capacity(size)),
+        };
+        pool.fill(size)?;
+        Ok(pool)
+    }
+}
+
+/// Pre-created, pre-configured sockets for outgoing connections.
+///
+/// UDT cannot reuse a socket once its connection has been closed, so the cost
+/// of creating sockets is amortized by creating them ahead of time instead,
+/// e.g. with `fill` between bursts of short-lived connections. Each socket is
+/// used for exactly one connection.
+pub struct UdtSocketPool {
+    builder: UdtBuilder,
+    family: AddrFamily,
+    idle: Mutex<Vec<UdtSocket>>,
+}
+
+impl UdtSocketPool {
+    /// Creates sockets until `size` are idle in the pool.
+    pub fn fill(&self, size: usize) -> Result<()> {
+        let mut idle = self.idle.lock().unwrap();
+        while idle.len() < size {
+            idle.push(self.new_socket()?);
+        }
+        Ok(())
+    }
+    /// Number of sockets ready to connect without creating a new one.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+    /// Connects to `remote` with a socket from the pool, or a new one if the
+    /// pool is empty. Only addresses of the pool's family are tried.
+    pub fn connect<A: ToSocketAddrs>(&self, remote: A) -> Result<UdtStream> {
+        let mut last_err = UdtError::SockFail("invalid address".to_string());
+        if let Ok(addrs) = remote.to_socket_addrs() {
+            for addr in addrs.filter(|addr| self.matches(addr)) {
+                let socket = match self.idle.lock().unwrap().pop() {
+                    Some(socket) => socket,
+                    None => self.new_socket()?,
+                };
+                match socket.connect(addr) {
+                    Ok(()) => return Ok(UdtStream::new(socket)),
+                    Err(e) => {
+                        close_on_drop(socket);
+                        last_err = e;
+                    }
+                }
+            }
+        }
+        Err(last_err)
+    }
+    fn matches(&self, addr: &SocketAddr) -> bool {
+        match self.family {
+            AddrFamily::Ipv4 => addr.is_ipv4(),
+            AddrFamily::Ipv6 => addr.is_ipv6(),
+        }
+    }
+    fn new_socket(&self) -> Result<UdtSocket> {
+        let socket = match self.family {
+            AddrFamily::Ipv4 => UdtSocket::new_ipv4()?,
+            AddrFamily::Ipv6 => UdtSocket::new_ipv6()?,
+        };
+        close_on_err(socket, self.builder.config_socket(&socket))?;
+        Ok(socket)
+    }
+}
+
+impl Drop for UdtSocketPool {
+    fn drop(&mut self) {
+        for socket in self.idle.lock().unwrap().drain(..) {
+            close_on_drop(socket);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// Settings for `UdtStream::enable_loss_based_rate_control`.
+///
+/// Bandwidths are in bytes per second, like `set_maxbw`. The limit starts at
+/// `max_bw`. Every `interval` it is multiplied by `decrease` if packets were
+/// lost since the last check, and raised by `increase` otherwise, staying
+/// within `min_bw..=max_bw`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateControlConfig {
+    pub min_bw: i64,
+    pub max_bw: i64,
+    pub decrease: f64,
+    pub increase: i64,
+    pub interval: Duration,
+}
+
+impl Default for RateControlConfig {
+    /// 1 Mbit/s to 1 Gbit/s, halving on loss and adding 10 Mbit/s per loss
+    /// free tenth of a second.
+    fn default() -> Self {
+        Self {
+            min_bw: 125_000,
+            max_bw: 125_000_000,
+            decrease: 0.5,
+            increase: 1_250_000,
+            interval: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RateControlConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.min_bw <= 0 || self.min_bw > self.max_bw {
+            return Err(format!(
+                "bandwidth bounds {}..={} are not a positive range",
+                self.min_bw, self.max_bw
+            ));
+        }
+        if !(self.decrease > 0.0 && self.decrease < 1.0) {
+            return Err(format!(
+                "decrease factor {} is not in (0, 1)",
+                self.decrease
+            ));
+        }
+        if self.increase <= 0 {
+            return Err(format!("increase of {} is not positive", self.increase));
+        }
+        if self.interval == Duration::from_secs(0) {
+            return Err("interval is zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The additive increase, multiplicative decrease step behind the controller,
+// kept apart from the socket so it can be driven with made up loss counts.
+pub(crate) struct RateController {
+    config: RateControlConfig,
+    limit: i64,
+    lost_total: i64,
+}
+
+impl RateController {
+    // `lost_total` is the send loss counter the first update is compared to.
+    pub(crate) fn new(config: RateControlConfig, lost_total: i64) -> Self {
+        Self {
+            config,
+            limit: config.max_bw,
+            lost_total,
+        }
+    }
+    // Takes the socket's total send loss count and returns the new limit.
+    pub(crate) fn update(&mut self, lost_total: i64) -> i64 {
+        let lost = lost_total - self.lost_total;
+        self.lost_total = lost_total;
+        let limit = if lost > 0 {
+            (self.limit as f64 * self.config.decrease) as i64
+        } else {
+            self.limit.saturating_add(self.config.increase)
+        };
+        self.limit = limit.max(self.config.min_bw).min(self.config.max_bw);
+        self.limit
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{error::UdtError, Epoll, UdtSocket, UdtStatus, ASYNC_WAIT_TIMEOUT_MS};
+
+use futures::task::Waker;
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+type Result<T> = std::result::Result<T, UdtError>;
+
+// Started by the first async wait and shared by all of them afterwards.
+static REACTOR: Mutex<Option<Arc<Reactor>>> = Mutex::new(None);
+
+// Wakes `waker` once `socket` is ready for `event`, or has failed.
+pub(crate) fn wake_when_ready(
+    socket: &UdtSocket,
+    event: &udt_sys::EPOLLOpt,
+    waker: Waker,
+) -> Result<()> {
+    reactor()?.register(socket, event, waker)
+}
+
+// Drops the wakers registered for `socket`, e.g. before it is closed.
+pub(crate) fn deregister(socket: &UdtSocket) {
+    let reactor = REACTOR.lock().unwrap().clone();
+    if let Some(reactor) = reactor {
+        reactor.deregister(socket);
+    }
+}
+
+// Drops only the registrations of `waker` for `socket`, leaving other tasks
+// waiting on the same socket in place.
+pub(crate) fn withdraw(socket: &UdtSocket, waker: &Waker) {
+    let reactor = REACTOR.lock().unwrap().clone();
+    if let Some(reactor) = reactor {
+        reactor.withdraw(socket, waker);
+    }
+}
+
+fn reactor() -> Result<Arc<Reactor>> {
+    let mut global = REACTOR.lock().unwrap();
+    if let Some(reactor) = global.as_ref() {
+        return Ok(reactor.clone());
+    }
+    let reactor = Arc::new(Reactor {
+        state: Mutex::new(State {
+            epoll: Epoll::new()?,
+            waiters: HashMap::new(),
+        }),
+        registered: Condvar::new(),
+    });
+    {
+        let reactor = reactor.clone();
+        thread::spawn(move || reactor.run());
+    }
+    *global = Some(reactor.clone());
+    Ok(reactor)
+}
+
+// A single epoll set and thread serving every pending async read, write,
+// flush and accept.
+struct Reactor {
+    state: Mutex<State>,
+    // Signalled on registration, so the thread can sleep while nothing waits.
+    registered: Condvar,
+}
+
+struct State {
+    epoll: Epoll,
+    waiters: HashMap<i32, Waiters>,
+}
+
+// Several tasks may wait on one socket, e.g. concurrent accepts on a
+// listener, so each direction keeps a waker per task.
+struct Waiters {
+    socket: UdtSocket,
+    read: Vec<Waker>,
+    write: Vec<Waker>,
+}
+
+impl Waiters {
+    // What the socket is currently added to the epoll set with. Errors are
+    // always included, so failed connections wake their tasks right away.
+    fn event(&self) -> udt_sys::EPOLLOpt {
+        let err = udt_sys::EPOLLOpt::UDT_EPOLL_ERR;
+        match (!self.read.is_empty(), !self.write.is_empty()) {
+            (true, true) => {
+                udt_sys::EPOLLOpt::UDT_EPOLL_IN | udt_sys::EPOLLOpt::UDT_EPOLL_OUT | err
+            }
+            (true, false) => udt_sys::EPOLLOpt::UDT_EPOLL_IN | err,
+            (false, true) => udt_sys::EPOLLOpt::UDT_EPOLL_OUT | err,
+            (false, false) => udt_sys::EPOLLOpt(0),
+        }
+    }
+    fn wake(self) {
+        for waker in self.read.into_iter().chain(self.write) {
+            waker.wake();
+        }
+    }
+}
+
+// Adds `waker` unless the same task is already waiting, as it is when a
+// pending future is polled again.
+fn add_waker(wakers: &mut Vec<Waker>, waker: Waker) {
+    match wakers.iter_mut().find(|w| w.will_wake(&waker)) {
+        Some(w) => *w = waker,
+        None => wakers.push(waker),
+    }
+}
+
+impl State {
+    fn remove(&mut self, id: i32) -> Option<Waiters> {
+        let waiters = self.waiters.remove(&id)?;
+        let _ = self.epoll.remove(&waiters.socket, &waiters.event());
+        Some(waiters)
+    }
+    // Re-adds `socket` to the epoll set after its combined event changed,
+    // so the set's counts stay right.
+    fn update(&mut self, socket: &UdtSocket, before: udt_sys::EPOLLOpt) -> Result<()> {
+        let after = match self.waiters.get(&socket.id) {
+            Some(waiters) => waiters.event(),
+            None => udt_sys::EPOLLOpt(0),
+        };
+        if after == before {
+            return Ok(());
+        }
+        if before != udt_sys::EPOLLOpt(0) {
+            let _ = self.epoll.remove(socket, &before);
+        }
+        if after == udt_sys::EPOLLOpt(0) {
+            self.waiters.remove(&socket.id);
+        } else if let Err(e) = self.epoll.add(socket, &after) {
+            self.waiters.remove(&socket.id);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl Reactor {
+    fn register(&self, socket: &UdtSocket, event: &udt_sys::EPOLLOpt, waker: Waker) -> Result<()> {
+        let read = *event & udt_sys::EPOLLOpt::UDT_EPOLL_IN == udt_sys::EPOLLOpt::UDT_EPOLL_IN;
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let waiters = state.waiters.entry(socket.id).or_insert(Waiters {
+            socket: *socket,
+            read: Vec::new(),
+            write: Vec::new(),
+        });
+        let before = waiters.event();
+        if read {
+            add_waker(&mut waiters.read, waker);
+        } else {
+            add_waker(&mut waiters.write, waker);
+        }
+        state.update(socket, before)?;
+        self.registered.notify_one();
+        Ok(())
+    }
+    fn deregister(&self, socket: &UdtSocket) {
+        self.state.lock().unwrap().remove(socket.id);
+    }
+    fn withdraw(&self, socket: &UdtSocket, waker: &Waker) {
+        let mut state = self.state.lock().unwrap();
+        let waiters = match state.waiters.get_mut(&socket.id) {
+            Some(waiters) => waiters,
+            None => return,
+        };
+        let before = waiters.event();
+        waiters.read.retain(|w| !w.will_wake(waker));
+        waiters.write.retain(|w| !w.will_wake(waker));
+        let _ = state.update(socket, before);
+    }
+    fn run(&self) {
+        let mut last_sweep = Instant::now();
+        loop {
+            // The epoll set may grow while we wait without the lock. Sockets
+            // beyond the snapshot's capacity are picked up on the next round.
+            let (id, num_rd_sock, num_wr_sock) = {
+                let mut state = self.state.lock().unwrap();
+                while state.waiters.is_empty() {
+                    state = self.registered.wait(state).unwrap();
+                }
+                let capacity = state.epoll.num_rd_sock + state.epoll.num_wr_sock;
+                (state.epoll.id, capacity, capacity)
+            };
+            let timeout = ASYNC_WAIT_TIMEOUT_MS.load(Ordering::Relaxed) as i64;
+            let ready = Epoll::wait_on(id, num_rd_sock, num_wr_sock, timeout);
+            let mut state = self.state.lock().unwrap();
+            let mut woken: Vec<Waiters> = match ready {
+                // A failed socket shows up in both sets, and a spurious wake-up
+                // only costs a poll, so both directions are woken either way.
+                Ok((readable, writable)) => readable
+                    .into_iter()
+                    .chain(writable)
+                    .filter_map(|id| state.remove(id))
+                    .collect(),
+                Err(UdtError::Timeout(_)) => Vec::new(),
+                // Let every task poll again and see the error for itself.
+                Err(_) => {
+                    let ids: Vec<i32> = state.waiters.keys().copied().collect();
+                    ids.into_iter().filter_map(|id| state.remove(id)).collect()
+                }
+            };
+            // UDT drops closed sockets from the set without reporting them, so
+            // look for waiters that will never become ready. This runs on its
+            // own schedule, as other sockets may keep every wait from timing out.
+            if last_sweep.elapsed() >= Duration::from_millis(timeout as u64) {
+                last_sweep = Instant::now();
+                let dead: Vec<i32> = state
+                    .waiters
+                    .values()
+                    .filter(|waiters| {
+                        matches!(
+                            waiters.socket.get_state(),
+                            UdtStatus::Broken
+                                | UdtStatus::Closing
+                                | UdtStatus::Closed
+                                | UdtStatus::NonExist
+                        )
+                    })
+                    .map(|waiters| waiters.socket.id)
+                    .collect();
+                woken.extend(dead.into_iter().filter_map(|id| state.remove(id)));
+            }
+            drop(state);
+            for waiters in woken {
+                waiters.wake();
+            }
+        }
+    }
+}
+
+/// A socket registration held by the async reactor, see `reactor_debug`.
+#[cfg(feature = "debug-reactor")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReactorEntry {
+    pub socket: i32,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// Lists every socket the async types are currently waiting on.
+#[cfg(feature = "debug-reactor")]
+pub fn reactor_debug() -> Vec<ReactorEntry> {
+    let reactor = match REACTOR.lock().unwrap().clone() {
+        Some(reactor) => reactor,
+        None => return Vec::new(),
+    };
+    let state = reactor.state.lock().unwrap();
+    state
+        .waiters
+        .values()
+        .map(|waiters| ReactorEntry {
+            socket: waiters.socket.id,
+            read: !waiters.read.is_empty(),
+            write: !waiters.write.is_empty(),
+        })
+        .collect()
+}
@@ -0,0 +1,131 @@
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    task::{Context, Poll},
+};
+
+use std::{
+    io::{self, Read, Write},
+    pin::Pin,
+    time::Instant,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IoOp {
+    Read,
+    Write,
+}
+
+/// A single completed read or write, with the number of bytes moved.
+#[derive(Copy, Clone, Debug)]
+pub struct IoRecord {
+    pub op: IoOp,
+    pub size: usize,
+    pub at: Instant,
+}
+
+/// Pass-through wrapper that records the size and time of every successful
+/// read and write on the inner stream. Meant as a test-support utility.
+#[derive(Debug)]
+pub struct RecordingStream<S> {
+    inner: S,
+    records: Vec<IoRecord>,
+}
+
+impl<S> RecordingStream<S> {
+    pub fn new(inner: S) -> RecordingStream<S> {
+        RecordingStream {
+            inner,
+            records: Vec::new(),
+        }
+    }
+    pub fn records(&self) -> &[IoRecord] {
+        &self.records
+    }
+    pub fn read_sizes(&self) -> Vec<usize> {
+        self.sizes(IoOp::Read)
+    }
+    pub fn write_sizes(&self) -> Vec<usize> {
+        self.sizes(IoOp::Write)
+    }
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+    fn sizes(&self, op: IoOp) -> Vec<usize> {
+        self.records
+            .iter()
+            .filter(|record| record.op == op)
+            .map(|record| record.size)
+            .collect()
+    }
+    fn record(&mut self, op: IoOp, size: usize) {
+        self.records.push(IoRecord {
+            op,
+            size,
+            at: Instant::now(),
+        });
+    }
+}
+
+impl<S: Read> Read for RecordingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.record(IoOp::Read, size);
+        Ok(size)
+    }
+}
+
+impl<S: Write> Write for RecordingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.record(IoOp::Write, size);
+        Ok(size)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for RecordingStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(size)) = result {
+            this.record(IoOp::Read, size);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for RecordingStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(size)) = result {
+            this.record(IoOp::Write, size);
+        }
+        result
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
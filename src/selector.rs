@@ -0,0 +1,119 @@
+use crate::{error::UdtError, Epoll, Readiness, UdtSocket};
+
+use std::{collections::HashMap, convert::TryInto, time::Duration};
+
+type Result<T> = std::result::Result<T, UdtError>;
+
+/// Which readiness a socket is registered with a `UdtSelector` for.
+///
+/// Every interest also reports errors (`UDT_EPOLL_ERR`) through
+/// `Readiness::error`; `Error` watches for nothing else.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    Write,
+    ReadWrite,
+    Error,
+}
+
+impl Interest {
+    fn event(self) -> udt_sys::EPOLLOpt {
+        let err = udt_sys::EPOLLOpt::UDT_EPOLL_ERR;
+        match self {
+            Interest::Read => udt_sys::EPOLLOpt::UDT_EPOLL_IN | err,
+            Interest::Write => udt_sys::EPOLLOpt::UDT_EPOLL_OUT | err,
+            Interest::ReadWrite => {
+                udt_sys::EPOLLOpt::UDT_EPOLL_IN | udt_sys::EPOLLOpt::UDT_EPOLL_OUT | err
+            }
+            Interest::Error => err,
+        }
+    }
+    fn read(self) -> bool {
+        matches!(self, Interest::Read | Interest::ReadWrite)
+    }
+    fn write(self) -> bool {
+        matches!(self, Interest::Write | Interest::ReadWrite)
+    }
+}
+
+/// Waits on many UDT sockets at once, like `select` for the blocking API.
+///
+/// Register the sockets of streams or listeners (see `UdtStream::as_udt_socket`)
+/// and call `select` to block until some of them are ready. The selector does
+/// not own the sockets; deregister them before closing.
+pub struct UdtSelector {
+    epoll: Epoll,
+    registered: HashMap<i32, (UdtSocket, Interest)>,
+}
+
+impl UdtSelector {
+    pub fn new() -> Result<UdtSelector> {
+        Ok(UdtSelector {
+            epoll: Epoll::new()?,
+            registered: HashMap::new(),
+        })
+    }
+    /// Registers `socket`, replacing its previous interest if it was registered already.
+    pub fn register(&mut self, socket: UdtSocket, interest: Interest) -> Result<()> {
+        self.deregister(socket)?;
+        self.epoll.add(&socket, &interest.event())?;
+        self.registered.insert(socket.id, (socket, interest));
+        Ok(())
+    }
+    /// Stops watching `socket`. Sockets that were not registered are ignored.
+    pub fn deregister(&mut self, socket: UdtSocket) -> Result<()> {
+        if let Some((socket, interest)) = self.registered.remove(&socket.id) {
+            self.epoll.remove(&socket, &interest.event())?;
+        }
+        Ok(())
+    }
+    /// Whether `socket` is currently registered.
+    pub fn contains(&self, socket: &UdtSocket) -> bool {
+        self.registered.contains_key(&socket.id)
+    }
+    /// Number of sockets in the epoll set that UDT may report as readable.
+    ///
+    /// UDT lists sockets with errors as both readable and writable, and every
+    /// interest includes errors, so this counts every registered socket.
+    pub fn num_read_sockets(&self) -> usize {
+        self.epoll.num_rd_sock
+    }
+    /// Number of sockets in the epoll set that UDT may report as writable,
+    /// counted like `num_read_sockets`.
+    pub fn num_write_sockets(&self) -> usize {
+        self.epoll.num_wr_sock
+    }
+    /// Blocks until at least one registered socket is ready, or `timeout`
+    /// passes, and returns the ready sockets. `None` waits forever; a timeout
+    /// with nothing ready returns an empty `Vec`.
+    pub fn select(&self, timeout: Option<Duration>) -> Result<Vec<(UdtSocket, Readiness)>> {
+        let timeout = match timeout {
+            Some(timeout) => timeout.as_millis().try_into().unwrap_or(i64::MAX),
+            None => -1,
+        };
+        let (readable, writable) = match self.epoll.wait(timeout) {
+            Ok(ready) => ready,
+            Err(UdtError::Timeout(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        // UDT lists sockets with errors as both readable and writable, so only
+        // report the directions each socket was registered for.
+        let mut ready: Vec<(UdtSocket, Readiness)> = Vec::new();
+        for id in readable.iter().chain(writable.iter()) {
+            if ready.iter().any(|(socket, _)| socket.id == *id) {
+                continue;
+            }
+            if let Some(&(socket, interest)) = self.registered.get(id) {
+                let readiness = Readiness {
+                    readable: interest.read() && readable.contains(id),
+                    writable: interest.write() && writable.contains(id),
+                    error: socket.has_error(),
+                };
+                if readiness != Readiness::default() {
+                    ready.push((socket, readiness));
+                }
+            }
+        }
+        Ok(ready)
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{close_on_drop, error::UdtError, UdtListener, UdtSocket, UdtStream};
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+type Result<T> = std::result::Result<T, UdtError>;
+
+// Sockets whose handlers are still running, with the flag their stream shares
+// to decide who closes them, signalled whenever one finishes.
+type Active = Arc<(Mutex<HashMap<UdtSocket, Arc<AtomicBool>>>, Condvar)>;
+
+impl UdtListener {
+    /// Accepts connections on a background thread and runs `handler` for each
+    /// one on its own thread, until the returned handle is shut down.
+    pub fn serve<F>(self, handler: F) -> ServerHandle
+    where
+        F: Fn(UdtStream, SocketAddr) + Send + Sync + 'static,
+    {
+        let socket = self.socket;
+        // Connections already taken off UDT's backlog by `pending` go first.
+        let queued: Vec<_> = self.shared.queued.lock().unwrap().drain(..).collect();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let active: Active = Arc::new((Mutex::new(HashMap::new()), Condvar::new()));
+        let acceptor = {
+            let stopping = stopping.clone();
+            let active = active.clone();
+            let handler = Arc::new(handler);
+            thread::spawn(move || {
+                let mut queued = queued.into_iter();
+                // Closing the listener wakes the blocked accept with an error.
+                while let Ok((peer, addr)) = queued.next().map_or_else(|| socket.accept(), Ok) {
+                    if stopping.load(Ordering::SeqCst) {
+                        close_on_drop(peer);
+                        break;
+                    }
+                    let closed = Arc::new(AtomicBool::new(false));
+                    active.0.lock().unwrap().insert(peer, closed.clone());
+                    let active = active.clone();
+                    let handler = handler.clone();
+                    thread::spawn(move || {
+                        let _finished = Finished { active, peer };
+                        let mut stream = UdtStream::new(peer);
+                        stream.closed = Some(closed);
+                        handler(stream, addr);
+                    });
+                }
+            })
+        };
+        ServerHandle {
+            listener: self,
+            stopping,
+            active,
+            acceptor,
+        }
+    }
+}
+
+// Removes a connection from the active set when its handler returns or panics.
+struct Finished {
+    active: Active,
+    peer: UdtSocket,
+}
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let (lock, finished) = &*self.active;
+        lock.lock().unwrap().remove(&self.peer);
+        finished.notify_all();
+    }
+}
+
+/// A running server started with `UdtListener::serve`.
+///
+/// Dropping the handle leaves running handlers alone. It only stops accepting
+/// once no handle to the listener from `UdtListener::try_clone` remains either;
+/// `shutdown` stops accepting regardless and drains the handlers.
+pub struct ServerHandle {
+    listener: UdtListener,
+    stopping: Arc<AtomicBool>,
+    active: Active,
+    acceptor: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// Number of connections whose handlers are still running.
+    pub fn active_connections(&self) -> usize {
+        self.active.0.lock().unwrap().len()
+    }
+    /// Stops accepting, waits up to `timeout` for running handlers to return,
+    /// then aborts the connections that are still open.
+    ///
+    /// Returns how many connections had to be aborted.
+    pub fn shutdown(self, timeout: Duration) -> Result<usize> {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.listener.close()?;
+        let _ = self.acceptor.join();
+        let deadline = Instant::now() + timeout;
+        let (lock, finished) = &*self.active;
+        let mut active = lock.lock().unwrap();
+        while !active.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            active = finished.wait_timeout(active, deadline - now).unwrap().0;
+        }
+        // Closing the sockets makes the handlers' pending reads and writes fail
+        // so they can return. Their streams see the flag and skip their own
+        // close, as do those of handlers already closing them.
+        for (socket, closed) in active.iter() {
+            if !closed.swap(true, Ordering::SeqCst) {
+                let _ = socket.set_linger(0);
+                close_on_drop(*socket);
+            }
+        }
+        Ok(active.len())
+    }
+}
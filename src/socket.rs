@@ -1,28 +1,68 @@
-use crate::error;
+use crate::{error, Epoll};
 
 use error::UdtError;
 use os_socketaddr::{self, OsSocketAddr};
 use udt_sys::{self, sockaddr};
 
 use std::{
+    cmp::Ordering,
     convert::TryInto,
     ffi::c_void,
+    fmt::{self, Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    io::{self, Read},
     mem,
     net::{SocketAddr, ToSocketAddrs},
     os::raw::{c_char, c_int},
+    time::Duration,
 };
 
 #[cfg(target_family = "unix")]
-use libc::{linger, AF_INET, AF_INET6, SOCK_STREAM};
+use libc::{linger, AF_INET, AF_INET6, SOCK_DGRAM, SOCK_STREAM};
 
 #[cfg(target_os = "windows")]
 use winapi::{
     shared::ws2def::{AF_INET, AF_INET6},
-    um::winsock2::{linger, SOCK_STREAM},
+    um::winsock2::{linger, SOCK_DGRAM, SOCK_STREAM},
 };
 
 type Result<T> = std::result::Result<T, UdtError>;
 
+/// Smallest MSS this crate accepts, checked before the value reaches UDT.
+///
+/// It is a conservative floor of our own, leaving room for the IP and UDP
+/// headers (28 bytes) plus a handshake packet (48 bytes). UDT's own check may
+/// allow somewhat smaller values.
+pub const MIN_MSS: i32 = 76;
+
+// UDT timeouts are in milliseconds; saturate anything too long to represent.
+fn duration_millis(duration: Duration) -> i32 {
+    duration.as_millis().try_into().unwrap_or(i32::MAX)
+}
+
+// Puts back an option changed for a single call. The call's own result matters
+// more, so a failed restore is only reported through `log`.
+fn restore_opt(result: Result<()>) {
+    if let Err(_e) = result {
+        #[cfg(feature = "log")]
+        log::warn!("failed to restore UDT socket option: {}", _e);
+    }
+}
+
+// UDT lingers in whole seconds, so round up; `None` and zero disable linger.
+pub(crate) fn linger_secs(linger: Option<Duration>) -> i32 {
+    match linger {
+        None => 0,
+        Some(linger) => {
+            let mut secs = linger.as_secs();
+            if linger.subsec_nanos() > 0 {
+                secs += 1;
+            }
+            secs.try_into().unwrap_or(i32::MAX)
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum UdtStatus {
     Init,
@@ -36,27 +76,217 @@ pub enum UdtStatus {
     NonExist,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Readiness of a socket as reported by `UdtSocket::poll_readiness`.
+///
+/// `error` is set once the connection has failed or been closed; reads and
+/// writes then fail right away instead of blocking.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    pub error: bool,
+}
+
+/// UDT's performance counters for a socket, see `UdtSocket::perfmon`.
+///
+/// The `_total` counters cover the socket's whole life. The other counters and
+/// rates cover the time since the last `perfmon` call that cleared them, and
+/// the rest are instant values.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PerfMon {
+    pub ms_timestamp: i64,
+    pub pkt_sent_total: i64,
+    pub pkt_recv_total: i64,
+    pub pkt_snd_loss_total: i32,
+    pub pkt_rcv_loss_total: i32,
+    pub pkt_retrans_total: i32,
+    pub pkt_sent_ack_total: i32,
+    pub pkt_recv_ack_total: i32,
+    pub pkt_sent_nak_total: i32,
+    pub pkt_recv_nak_total: i32,
+    pub us_snd_duration_total: i64,
+    pub pkt_sent: i64,
+    pub pkt_recv: i64,
+    pub pkt_snd_loss: i32,
+    pub pkt_rcv_loss: i32,
+    pub pkt_retrans: i32,
+    pub pkt_sent_ack: i32,
+    pub pkt_recv_ack: i32,
+    pub pkt_sent_nak: i32,
+    pub pkt_recv_nak: i32,
+    pub mbps_send_rate: f64,
+    pub mbps_recv_rate: f64,
+    pub us_snd_duration: i64,
+    pub us_pkt_snd_period: f64,
+    pub pkt_flow_window: i32,
+    pub pkt_congestion_window: i32,
+    pub pkt_flight_size: i32,
+    pub ms_rtt: f64,
+    pub mbps_bandwidth: f64,
+    pub byte_avail_snd_buf: i32,
+    pub byte_avail_rcv_buf: i32,
+}
+
+impl From<udt_sys::UDT_TRACEINFO> for PerfMon {
+    fn from(info: udt_sys::UDT_TRACEINFO) -> Self {
+        PerfMon {
+            ms_timestamp: info.msTimeStamp,
+            pkt_sent_total: info.pktSentTotal,
+            pkt_recv_total: info.pktRecvTotal,
+            pkt_snd_loss_total: info.pktSndLossTotal,
+            pkt_rcv_loss_total: info.pktRcvLossTotal,
+            pkt_retrans_total: info.pktRetransTotal,
+            pkt_sent_ack_total: info.pktSentACKTotal,
+            pkt_recv_ack_total: info.pktRecvACKTotal,
+            pkt_sent_nak_total: info.pktSentNAKTotal,
+            pkt_recv_nak_total: info.pktRecvNAKTotal,
+            us_snd_duration_total: info.usSndDurationTotal,
+            pkt_sent: info.pktSent,
+            pkt_recv: info.pktRecv,
+            pkt_snd_loss: info.pktSndLoss,
+            pkt_rcv_loss: info.pktRcvLoss,
+            pkt_retrans: info.pktRetrans,
+            pkt_sent_ack: info.pktSentACK,
+            pkt_recv_ack: info.pktRecvACK,
+            pkt_sent_nak: info.pktSentNAK,
+            pkt_recv_nak: info.pktRecvNAK,
+            mbps_send_rate: info.mbpsSendRate,
+            mbps_recv_rate: info.mbpsRecvRate,
+            us_snd_duration: info.usSndDuration,
+            us_pkt_snd_period: info.usPktSndPeriod,
+            pkt_flow_window: info.pktFlowWindow,
+            pkt_congestion_window: info.pktCongestionWindow,
+            pkt_flight_size: info.pktFlightSize,
+            ms_rtt: info.msRTT,
+            mbps_bandwidth: info.mbpsBandwidth,
+            byte_avail_snd_buf: info.byteAvailSndBuf,
+            byte_avail_rcv_buf: info.byteAvailRcvBuf,
+        }
+    }
+}
+
+/// Address family a socket was created with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddrFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl Display for AddrFamily {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrFamily::Ipv4 => write!(f, "ipv4"),
+            AddrFamily::Ipv6 => write!(f, "ipv6"),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
 pub struct UdtSocket {
     pub id: i32,
+    family: AddrFamily,
+}
+
+// A socket is identified by its id alone; the family is implied by it.
+impl PartialEq for UdtSocket {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for UdtSocket {}
+
+impl Hash for UdtSocket {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialOrd for UdtSocket {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for UdtSocket {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+// The family is known from creation, so it shows up even before bind when
+// local_addr would still fail.
+impl Debug for UdtSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdtSocket")
+            .field("id", &self.id)
+            .field("family", &format_args!("{}", self.family))
+            .finish()
+    }
 }
 
 //General methods
 impl UdtSocket {
     pub fn new_ipv4() -> Result<Self> {
         let sock = unsafe { udt_sys::udt_socket(AF_INET, SOCK_STREAM, 0) };
+        let family = AddrFamily::Ipv4;
         if sock == unsafe { udt_sys::UDT_INVALID_SOCK } {
-            error::get_error(Self { id: 0 })
+            error::get_error(Self { id: 0, family })
         } else {
-            Ok(Self { id: sock })
+            Ok(Self { id: sock, family })
         }
     }
     pub fn new_ipv6() -> Result<Self> {
         let sock = unsafe { udt_sys::udt_socket(AF_INET6, SOCK_STREAM, 0) };
+        let family = AddrFamily::Ipv6;
+        if sock == unsafe { udt_sys::UDT_INVALID_SOCK } {
+            error::get_error(Self { id: 0, family })
+        } else {
+            Ok(Self { id: sock, family })
+        }
+    }
+    /*
+        Message-mode socket: every send is delivered as one message with its
+        boundaries kept. Use sendmsg and recvmsg on it instead of send and recv.
+    */
+    pub fn new_ipv4_dgram() -> Result<Self> {
+        let sock = unsafe { udt_sys::udt_socket(AF_INET, SOCK_DGRAM, 0) };
+        let family = AddrFamily::Ipv4;
+        if sock == unsafe { udt_sys::UDT_INVALID_SOCK } {
+            error::get_error(Self { id: 0, family })
+        } else {
+            Ok(Self { id: sock, family })
+        }
+    }
+    /*
+        Message-mode socket: every send is delivered as one message with its
+        boundaries kept. Use sendmsg and recvmsg on it instead of send and recv.
+    */
+    pub fn new_ipv6_dgram() -> Result<Self> {
+        let sock = unsafe { udt_sys::udt_socket(AF_INET6, SOCK_DGRAM, 0) };
+        let family = AddrFamily::Ipv6;
         if sock == unsafe { udt_sys::UDT_INVALID_SOCK } {
-            error::get_error(Self { id: 0 })
+            error::get_error(Self { id: 0, family })
         } else {
-            Ok(Self { id: sock })
+            Ok(Self { id: sock, family })
+        }
+    }
+    /*
+        Wrap a socket id created elsewhere. The family is read from the bound
+        address; a socket that is not bound yet is taken to be IPv4.
+    */
+    pub(crate) fn from_raw(id: i32) -> Self {
+        let socket = Self {
+            id,
+            family: AddrFamily::Ipv4,
+        };
+        match socket.local_addr() {
+            Ok(SocketAddr::V6(_)) => Self {
+                id,
+                family: AddrFamily::Ipv6,
+            },
+            _ => socket,
         }
     }
     pub fn bind(self, addr: SocketAddr) -> Result<Self> {
@@ -74,7 +304,13 @@ impl UdtSocket {
             return Ok(self);
         }
     }
+    /*
+        Tries each resolved address in turn until one connects, returning the
+        error of the last attempt if none does. Addresses of a different family
+        than the socket fail and are skipped like any other unreachable one.
+    */
     pub fn connect<A: ToSocketAddrs>(&self, addrs: A) -> Result<()> {
+        let mut last_err = UdtError::ConnFail("invalid address".to_string());
         if let Ok(addrs) = addrs.to_socket_addrs() {
             for addr in addrs {
                 let os_target: OsSocketAddr = addr.into();
@@ -86,13 +322,15 @@ impl UdtSocket {
                     )
                 };
                 if result == unsafe { udt_sys::UDT_ERROR } {
-                    return error::get_error(());
+                    if let Err(e) = error::get_error(()) {
+                        last_err = e;
+                    }
                 } else {
                     return Ok(());
                 }
             }
         }
-        Err(UdtError::ConnFail("invalid address".to_string()))
+        Err(last_err)
     }
     pub fn listen(&self, backlog: i32) -> Result<()> {
         let result = unsafe { udt_sys::udt_listen(self.id, backlog) };
@@ -106,6 +344,13 @@ impl UdtSocket {
 
 //Public operational methods
 impl UdtSocket {
+    /*
+        The address family this socket was created with. Accepted sockets
+        share the family of their listener.
+    */
+    pub fn family(&self) -> AddrFamily {
+        self.family
+    }
     pub fn local_addr(&self) -> Result<SocketAddr> {
         let mut addr = OsSocketAddr::new();
         let mut addrlen: c_int = addr.capacity() as i32;
@@ -122,6 +367,14 @@ impl UdtSocket {
             Ok(addr.into_addr().unwrap())
         }
     }
+    /*
+        The local endpoint of the UDP socket this UDT socket sends through.
+        UDT reports the multiplexer's address from getsockname, so sockets
+        sharing one UDP port through reuse_addr all return the same value here.
+    */
+    pub fn multiplexer_addr(&self) -> Result<SocketAddr> {
+        self.local_addr()
+    }
     pub fn peer_addr(&self) -> Result<SocketAddr> {
         let mut addr = OsSocketAddr::new();
         let mut addrlen: c_int = addr.capacity() as i32;
@@ -149,9 +402,17 @@ impl UdtSocket {
             )
         };
         if result == unsafe { udt_sys::UDT_INVALID_SOCK } {
-            error::get_error((Self { id: result }, "0.0.0.0:0".parse().unwrap()))
+            let socket = Self {
+                id: result,
+                family: self.family,
+            };
+            error::get_error((socket, "0.0.0.0:0".parse().unwrap()))
         } else {
-            Ok((Self { id: result }, addr.into_addr().unwrap()))
+            let socket = Self {
+                id: result,
+                family: self.family,
+            };
+            Ok((socket, addr.into_addr().unwrap()))
         }
     }
     pub fn close(self) -> Result<()> {
@@ -177,6 +438,11 @@ impl UdtSocket {
             Ok(result as usize)
         }
     }
+    /*
+        Receive into buf. Once the connection is gone and everything buffered
+        has been read, this fails with ConnLost, whether the peer closed or the
+        link broke; UDT does not tell the two apart.
+    */
     pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
         let result = unsafe {
             udt_sys::udt_recv(
@@ -192,9 +458,216 @@ impl UdtSocket {
             Ok(result as usize)
         }
     }
+    /*
+        Send the whole buffer, looping over partial sends until done or an error occurs.
+    */
+    pub fn send_all(&self, buf: &[u8]) -> Result<()> {
+        let mut sent = 0;
+        while sent < buf.len() {
+            sent += self.send(&buf[sent..])?;
+        }
+        Ok(())
+    }
+    /*
+        Send up to len bytes read from reader, in MSS-sized chunks, and return how
+        many were sent. Stops early if the reader reaches its end first.
+    */
+    pub fn send_from<R: Read>(&self, reader: &mut R, len: u64) -> Result<u64> {
+        let mut buf = vec![0; self.get_mss()?.max(MIN_MSS) as usize];
+        let mut sent = 0;
+        while sent < len {
+            let want = (len - sent).min(buf.len() as u64) as usize;
+            let size = match reader.read(&mut buf[..want]) {
+                Ok(0) => break,
+                Ok(size) => size,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.send_all(&buf[..size])?;
+            sent += size as u64;
+        }
+        Ok(sent)
+    }
+    /*
+        Fill the whole buffer, looping over partial receives until done or an error occurs.
+    */
+    pub fn recv_exact(&self, buf: &mut [u8]) -> Result<()> {
+        let mut received = 0;
+        while received < buf.len() {
+            match self.recv(&mut buf[received..])? {
+                0 => return Err(UdtError::ConnLost("connection closed".to_string())),
+                size => received += size,
+            }
+        }
+        Ok(())
+    }
+    /*
+        Send one message on a message-mode socket. The message is dropped if it
+        cannot be delivered within ttl milliseconds (-1 for never); with
+        inorder set it is only delivered after all earlier messages.
+    */
+    pub fn sendmsg(&self, buf: &[u8], ttl: i32, inorder: bool) -> Result<usize> {
+        let result = unsafe {
+            udt_sys::udt_sendmsg(
+                self.id,
+                buf as *const [u8] as *const c_char,
+                buf.len() as i32,
+                ttl,
+                inorder,
+            )
+        };
+        if result == unsafe { udt_sys::UDT_ERROR } {
+            error::get_error(0)
+        } else {
+            Ok(result as usize)
+        }
+    }
+    /*
+        Largest message sendmsg accepts before failing with LargeMsg. UDT caps a
+        message at the send buffer's size in packets, each carrying the MSS less
+        the IP and UDP headers (28 bytes) and the UDT header (16 bytes).
+    */
+    pub fn max_message_size(&self) -> Result<usize> {
+        let mss = self.get_mss()?;
+        let packets = self.get_sndbuf()? / (mss - 28);
+        Ok((packets * (mss - 44)).max(0) as usize)
+    }
+    /*
+        Bytes of payload the send buffer has room for right now. UDT reports the
+        buffer's size in bytes but its occupancy in packets, so the free packets
+        are converted to payload as in max_message_size.
+    */
+    pub(crate) fn snd_buf_room(&self) -> Result<usize> {
+        let mss = self.get_mss()?;
+        let packets = self.get_sndbuf()? / (mss - 28);
+        let free = packets - self.get_snddata()?;
+        Ok((free * (mss - 44)).max(0) as usize)
+    }
+    /*
+        Receive one message on a message-mode socket. UDT discards whatever part
+        of the message does not fit in buf.
+    */
+    pub fn recvmsg(&self, buf: &mut [u8]) -> Result<usize> {
+        let result = unsafe {
+            udt_sys::udt_recvmsg(self.id, buf as *mut [u8] as *mut c_char, buf.len() as i32)
+        };
+        if result == unsafe { udt_sys::UDT_ERROR } {
+            error::get_error(0)
+        } else {
+            Ok(result as usize)
+        }
+    }
+    /*
+        Receive without blocking, whatever the socket's receiving mode.
+        The mode is restored afterwards; returns None if no data is available.
+        The mode is switched for the whole socket meanwhile, so this must not
+        run concurrently with other receives on the same socket. A failure to
+        restore the mode is only logged, so received data is never lost.
+    */
+    pub fn try_recv(&self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let blocking = self.get_rcvsyn()?;
+        if blocking {
+            self.set_rcvsyn(false)?;
+        }
+        let result = self.recv(buf);
+        if blocking {
+            restore_opt(self.set_rcvsyn(true));
+        }
+        match result {
+            Ok(size) => Ok(Some(size)),
+            Err(UdtError::AsyncRcv(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /*
+        Receive with a timeout for this call only; the socket's receiving timeout is restored afterwards.
+        Returns None if nothing arrived in time. Like try_recv, this changes an
+        option of the whole socket while it runs, so it must not run
+        concurrently with other receives on the same socket.
+    */
+    pub fn recv_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>> {
+        let previous = self.get_rcvtimeo()?;
+        self.set_rcvtimeo(duration_millis(timeout))?;
+        let result = self.recv(buf);
+        restore_opt(self.set_rcvtimeo(previous));
+        match result {
+            Ok(size) => Ok(Some(size)),
+            Err(UdtError::Timeout(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /*
+        Send with a timeout for this call only; the socket's sending timeout is restored afterwards.
+        Returns None if the send buffer had no room in time. It must not run
+        concurrently with other sends on the same socket, for the same reason.
+    */
+    pub fn send_timeout(&self, buf: &[u8], timeout: Duration) -> Result<Option<usize>> {
+        let previous = self.get_sndtimeo()?;
+        self.set_sndtimeo(duration_millis(timeout))?;
+        let result = self.send(buf);
+        restore_opt(self.set_sndtimeo(previous));
+        match result {
+            Ok(size) => Ok(Some(size)),
+            Err(UdtError::Timeout(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /*
+        Current readiness for the given interest, without waiting or registering a waker.
+    */
+    pub fn poll_readiness(&self, interest: &udt_sys::EPOLLOpt) -> Result<Readiness> {
+        let mut epoll = Epoll::new()?;
+        epoll.add(self, interest)?;
+        match epoll.wait(0) {
+            Ok((rd, wr)) => Ok(Readiness {
+                readable: rd.contains(&self.id),
+                writable: wr.contains(&self.id),
+                error: self.has_error(),
+            }),
+            Err(UdtError::Timeout(_)) => Ok(Readiness::default()),
+            Err(e) => Err(e),
+        }
+    }
+    /*
+        UDT's performance counters. With clear set, the counters that are not
+        totals restart from zero afterwards.
+    */
+    pub fn perfmon(&self, clear: bool) -> Result<PerfMon> {
+        // Plain integers and floats, for which all zeroes is a valid value.
+        let mut info: udt_sys::UDT_TRACEINFO = unsafe { mem::zeroed() };
+        let result = unsafe { udt_sys::udt_perfmon(self.id, &mut info, clear) };
+        if result == unsafe { udt_sys::UDT_ERROR } {
+            error::get_error(PerfMon::default())
+        } else {
+            Ok(info.into())
+        }
+    }
 }
 //Get opt methods
 impl UdtSocket {
+    /// Raw getsockopt for options without a typed getter. Returns how many
+    /// bytes UDT wrote into `buf`.
+    ///
+    /// # Safety
+    ///
+    /// UDT writes the option's full value without checking the buffer length,
+    /// so `buf` must be at least as large as that value (8 bytes covers every
+    /// built-in option).
+    pub unsafe fn get_opt_raw(&self, opt: udt_sys::UDTOpt, buf: &mut [u8]) -> Result<usize> {
+        let mut len = buf.len() as i32;
+        let result = udt_sys::udt_getsockopt(
+            self.id,
+            0,
+            opt,
+            buf.as_mut_ptr() as *mut c_void,
+            &mut len as *mut i32,
+        );
+        if result == udt_sys::UDT_ERROR {
+            error::get_error(0)
+        } else {
+            Ok(len as usize)
+        }
+    }
     pub fn get_mss(&self) -> Result<i32> {
         let mut val = 0;
         let mut val_len = mem::size_of_val(&val) as i32;
@@ -465,6 +938,17 @@ impl UdtSocket {
             _ => unreachable!("unrecognized udt status"),
         }
     }
+    /*
+        Whether UDT flags the socket with UDT_EPOLL_ERR, or it is gone entirely.
+    */
+    pub(crate) fn has_error(&self) -> bool {
+        match self.get_event() {
+            Ok(event) => {
+                event & udt_sys::EPOLLOpt::UDT_EPOLL_ERR == udt_sys::EPOLLOpt::UDT_EPOLL_ERR
+            }
+            Err(_) => true,
+        }
+    }
     pub fn get_event(&self) -> Result<udt_sys::EPOLLOpt> {
         let mut val = 0;
         let mut val_len = mem::size_of_val(&val) as i32;
@@ -483,6 +967,10 @@ impl UdtSocket {
             Ok(udt_sys::EPOLLOpt(val as u32))
         }
     }
+    /*
+        Packets in the send buffer that the peer has not acknowledged yet. UDT
+        counts buffer occupancy in packets, unlike UDT_SNDBUF.
+    */
     pub fn get_snddata(&self) -> Result<i32> {
         let mut val = 0;
         let mut val_len = mem::size_of_val(&val) as i32;
@@ -501,6 +989,9 @@ impl UdtSocket {
             Ok(val)
         }
     }
+    /*
+        Packets received and not read yet, also counted in packets.
+    */
     pub fn get_rcvdata(&self) -> Result<i32> {
         let mut val = 0;
         let mut val_len = mem::size_of_val(&val) as i32;
@@ -522,11 +1013,42 @@ impl UdtSocket {
 }
 //Set opt methods
 impl UdtSocket {
+    /// Raw setsockopt for options without a typed setter.
+    ///
+    /// # Safety
+    ///
+    /// UDT reads the option's value from `buf` without checking its length, and
+    /// some options (such as `UDT_CC`) are read as pointers, so `buf` must hold
+    /// a valid value of the type the option expects.
+    ///
+    /// `UDT_CC` cannot be driven from Rust yet: it takes a factory for a C++
+    /// congestion control class, and `udt_sys` exports no constructor for one.
+    pub unsafe fn set_opt_raw(&self, opt: udt_sys::UDTOpt, buf: &[u8]) -> Result<()> {
+        let result = udt_sys::udt_setsockopt(
+            self.id,
+            0,
+            opt,
+            buf.as_ptr() as *const c_void,
+            buf.len() as i32,
+        );
+        if result == udt_sys::UDT_ERROR {
+            error::get_error(())
+        } else {
+            Ok(())
+        }
+    }
     /*
         Maximum packet size (bytes).
         Including all UDT, UDP, and IP headers. Default 1500 bytes.
+        Values below MIN_MSS are rejected with InvParam.
     */
     pub fn set_mss(&self, mss: i32) -> Result<()> {
+        if mss < MIN_MSS {
+            return Err(UdtError::InvParam(format!(
+                "MSS of {} bytes is below the minimum of {}",
+                mss, MIN_MSS
+            )));
+        }
         let result = unsafe {
             udt_sys::udt_setsockopt(
                 self.id,
@@ -707,6 +1229,13 @@ impl UdtSocket {
         }
     }
 
+    /*
+        Linger time on close() as a duration: None closes without lingering,
+        Some waits up to that long, rounded up to whole seconds.
+    */
+    pub fn set_linger_opt(&self, linger: Option<Duration>) -> Result<()> {
+        self.set_linger(linger_secs(linger))
+    }
     /*
         Rendezvous connection setup.
         Default false (no rendezvous mode).
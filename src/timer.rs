@@ -0,0 +1,76 @@
+use futures::task::Waker;
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+// Started by the first deadline and shared by all of them afterwards.
+static TIMER: Mutex<Option<Arc<Timer>>> = Mutex::new(None);
+
+// Wakes `waker` once `deadline` has passed.
+pub(crate) fn wake_at(deadline: Instant, waker: Waker) {
+    timer().add(deadline, waker);
+}
+
+fn timer() -> Arc<Timer> {
+    let mut global = TIMER.lock().unwrap();
+    if let Some(timer) = global.as_ref() {
+        return timer.clone();
+    }
+    let timer = Arc::new(Timer {
+        pending: Mutex::new(Vec::new()),
+        added: Condvar::new(),
+    });
+    {
+        let timer = timer.clone();
+        thread::spawn(move || timer.run());
+    }
+    *global = Some(timer.clone());
+    timer
+}
+
+// A single thread serving the deadlines of every pending async operation.
+// Entries are not withdrawn when their future finishes early; they only cost
+// a spurious wake-up at the deadline.
+struct Timer {
+    pending: Mutex<Vec<(Instant, Waker)>>,
+    // Signalled on every addition, which may be due before the current wait ends.
+    added: Condvar,
+}
+
+impl Timer {
+    fn add(&self, deadline: Instant, waker: Waker) {
+        let mut pending = self.pending.lock().unwrap();
+        // A task polled again before its deadline needs only one wake-up.
+        match pending
+            .iter_mut()
+            .find(|(at, w)| *at == deadline && w.will_wake(&waker))
+        {
+            Some(entry) => entry.1 = waker,
+            None => pending.push((deadline, waker)),
+        }
+        self.added.notify_one();
+    }
+    fn run(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let (due, rest): (Vec<_>, Vec<_>) = pending.drain(..).partition(|(at, _)| *at <= now);
+            *pending = rest;
+            if !due.is_empty() {
+                drop(pending);
+                for (_, waker) in due {
+                    waker.wake();
+                }
+                pending = self.pending.lock().unwrap();
+                continue;
+            }
+            pending = match pending.iter().map(|(at, _)| *at).min() {
+                Some(next) => self.added.wait_timeout(pending, next - now).unwrap().0,
+                None => self.added.wait(pending).unwrap(),
+            };
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{UdtBuilder, UdtListener, UdtStream};
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+};
+
+// Backlog of listeners opened through `Transport`, the same as std uses for TCP.
+const TRANSPORT_BACKLOG: i32 = 128;
+
+/// A connected stream of any transport, see `Transport`.
+pub trait Connection: Read + Write + Send {
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    fn close(self: Box<Self>) -> io::Result<()>;
+}
+
+/// A listener of any transport, see `Transport`.
+pub trait Listener: Send + Sync {
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, SocketAddr)>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// Opens connections and listeners, so code written against `dyn Transport`
+/// runs over TCP (`TcpTransport`) or UDT (a `UdtBuilder` and its options)
+/// unchanged.
+pub trait Transport: Send + Sync {
+    fn connect(&self, addr: SocketAddr) -> io::Result<Box<dyn Connection>>;
+    fn listen(&self, addr: SocketAddr) -> io::Result<Box<dyn Listener>>;
+}
+
+impl Connection for UdtStream {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(UdtStream::local_addr(self)?)
+    }
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(UdtStream::peer_addr(self)?)
+    }
+    fn close(self: Box<Self>) -> io::Result<()> {
+        Ok(UdtStream::close(*self)?)
+    }
+}
+
+impl Listener for UdtListener {
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, SocketAddr)> {
+        let (stream, addr) = UdtListener::accept(self)?;
+        Ok((Box::new(stream), addr))
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(UdtListener::local_addr(self)?)
+    }
+}
+
+impl Transport for UdtBuilder {
+    fn connect(&self, addr: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        let socket = self.connect_addr(addr)?;
+        Ok(Box::new(UdtStream::new(socket)))
+    }
+    fn listen(&self, addr: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        let builder = UdtBuilder {
+            opt_vec: self.opt_vec.clone(),
+        };
+        Ok(Box::new(builder.listen(addr, TRANSPORT_BACKLOG)?))
+    }
+}
+
+impl Connection for TcpStream {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+    fn close(self: Box<Self>) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<(Box<dyn Connection>, SocketAddr)> {
+        let (stream, addr) = TcpListener::accept(self)?;
+        Ok((Box::new(stream), addr))
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+/// The `Transport` for plain TCP from `std::net`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn connect(&self, addr: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpStream::connect(addr)?))
+    }
+    fn listen(&self, addr: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        Ok(Box::new(TcpListener::bind(addr)?))
+    }
+}
@@ -0,0 +1,43 @@
+// Crate tests run in parallel and each hold a startup, so UDT is never really
+// torn down there. This binary has a single test and owns the process, so each
+// cleanup below does shut UDT down before the next startup.
+use std::{
+    io::{Read, Write},
+    thread,
+};
+
+fn echo_once() {
+    let (listen, addr) = udt_rs::builder()
+        .set_reuse_addr(false)
+        .listen_with_addr("127.0.0.1:0", 1)
+        .expect("fail listen_with_addr()");
+    let handle = thread::spawn(move || {
+        let (mut peer, _peer_addr) = listen.accept().expect("fail accept()");
+        let mut buf = [0; 7];
+        peer.read_exact(&mut buf).expect("fail read()");
+        peer.write_all(&buf).expect("fail write()");
+        peer
+    });
+    let mut connect = udt_rs::builder()
+        .set_reuse_addr(false)
+        .connect(addr)
+        .expect("fail connect()");
+    connect.write_all(b"testing").expect("fail write()");
+    let mut buf = [0; 7];
+    connect.read_exact(&mut buf).expect("fail read()");
+    assert_eq!(&buf, b"testing");
+    let peer = handle.join().expect("fail join()");
+    assert!(connect.close().is_ok());
+    assert!(peer.close().is_ok());
+}
+
+#[test]
+fn test_startup_after_cleanup() {
+    for _ in 0..2 {
+        udt_rs::startup().expect("failed startup");
+        echo_once();
+        udt_rs::cleanup().expect("failed cleanup()");
+        // Nothing holds a startup any more, so UDT was torn down for real.
+        assert!(udt_rs::cleanup().is_err());
+    }
+}